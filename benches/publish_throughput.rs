@@ -0,0 +1,118 @@
+//! Compare publishing throughput against the mock broker in `tests/support`, which accepts any
+//! connection and discards published messages.
+//!
+//! - `publish` : `LocalChannel::publish` one by one, flushing every message.
+//! - `sink (flush every item)` : `PublishSink` with high-water mark 0.
+//! - `sink (default)` : `PublishSink` with `DEFAULT_HIGH_WATER_MARK`.
+//!
+//! Run by `cargo bench --bench publish_throughput`. `AMQPR_BENCH_COUNT` (100000 by default) and
+//! `AMQPR_BENCH_SIZE` (32 bytes by default) change the number and the size of messages.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate bytes;
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+use futures::{Future, Stream, Sink, stream};
+use futures::unsync::mpsc::UnboundedReceiver;
+use tokio_core::reactor::Core;
+
+use amqpr_codec::Frame;
+
+use bytes::Bytes;
+
+use amqpr::unsync::{connect_uri, LocalChannel, BoxedIncome, BoxedOutgo,
+                    DEFAULT_HIGH_WATER_MARK};
+use amqpr::errors::Error;
+
+use std::env;
+use std::rc::Rc;
+use std::time::Instant;
+
+use support::MockTuning;
+
+type Channel = LocalChannel<BoxedIncome, BoxedOutgo>;
+type Published = Box<Future<Item = (), Error = Rc<Error>>>;
+
+
+fn main() {
+    let count = env_or("AMQPR_BENCH_COUNT", 100_000);
+    let size = env_or("AMQPR_BENCH_SIZE", 32);
+    let body = Bytes::from(vec![0u8; size]);
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, mut received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let mut global = core.run(connect_uri(&uri, &handle)).unwrap();
+
+    println!("Publish {} messages of {} bytes", count, size);
+
+    let (g, local) = core.run(global.open_channel(1)).unwrap();
+    global = g;
+    let b = body.clone();
+    let published = stream::iter_ok(0..count)
+        .fold(local, move |local, _| local.publish(b.clone(), "", "bench"))
+        .map(|_| ());
+    bench(&mut core, "publish", count, &mut received, Box::new(published));
+
+    let (g, local) = core.run(global.open_channel(2)).unwrap();
+    global = g;
+    let published = publish_by_sink(local, body.clone(), count, 0);
+    bench(&mut core, "sink (flush every item)", count, &mut received, published);
+
+    let (_global, local) = core.run(global.open_channel(3)).unwrap();
+    let published = publish_by_sink(local, body.clone(), count, DEFAULT_HIGH_WATER_MARK);
+    bench(&mut core, "sink (default)", count, &mut received, published);
+}
+
+
+fn publish_by_sink(local: Channel, body: Bytes, count: usize, high_water_mark: usize) -> Published {
+    let (local, mut sink) = local.publish_sink("", "bench");
+    sink.set_high_water_mark(high_water_mark);
+    let items = stream::iter_ok((0..count).map(move |_| body.clone()));
+    Box::new(sink.send_all(items).map(move |_| drop(local)))
+}
+
+
+/// Measure time until the mock broker receives every message.
+fn bench(
+    core: &mut Core,
+    name: &str,
+    count: usize,
+    received: &mut UnboundedReceiver<Frame>,
+    published: Published,
+) {
+    let start = Instant::now();
+
+    let received = received
+        .by_ref()
+        .filter(support::is_publish)
+        .take(count as u64)
+        .for_each(|_| Ok(()));
+    let published = published.map_err(|e| println!("Error : {:?}", e));
+    core.run(published.join(received)).unwrap();
+
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+    println!(
+        "{:<24} : {:.3} sec ({:.0} messages/sec)",
+        name,
+        secs,
+        count as f64 / secs
+    );
+}
+
+
+fn env_or(name: &str, default: usize) -> usize {
+    match env::var(name) {
+        Ok(value) => value.parse().expect(name),
+        Err(_) => default,
+    }
+}
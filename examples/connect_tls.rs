@@ -0,0 +1,80 @@
+extern crate amqpr;
+extern crate futures;
+extern crate tokio_core;
+extern crate clap;
+
+use futures::Future;
+use tokio_core::reactor::Core;
+
+use clap::{App, Arg};
+
+use amqpr::unsync::{connect_amqp_uri_with_tls, TlsOption, Certificate};
+use amqpr::uri::AmqpUri;
+
+use std::fs::File;
+use std::io::Read;
+
+/// Connect to TLS enabled AMQP server (e.g. RabbitMQ listening on 5671 or a stunnel stand-in
+/// with self-signed certificate) and open a channel.
+fn main() {
+    let args = get_args();
+
+    let uri: AmqpUri = args.uri.parse().unwrap();
+
+    let mut option = TlsOption::new(args.domain.unwrap_or(uri.host.clone()));
+    option.verify_hostname = !args.no_verify_hostname;
+    if let Some(path) = args.ca_file {
+        let mut pem = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut pem).unwrap();
+        option.root_certificates.push(Certificate::from_pem(&pem).unwrap());
+    }
+
+    let mut core = Core::new().unwrap();
+
+    let fut = connect_amqp_uri_with_tls(&uri, &option, &core.handle())
+        .and_then(|global| global.open_channel(1))
+        .map(|(_global, local)| {
+            println!("Channel {} is opened over TLS", local.channel_id);
+        });
+
+    core.run(fut).unwrap();
+}
+
+
+struct Args {
+    uri: String,
+    ca_file: Option<String>,
+    domain: Option<String>,
+    no_verify_hostname: bool,
+}
+
+
+fn get_args() -> Args {
+    let matches = App::new("AMQP TLS connection checker")
+        .arg(
+            Arg::with_name("uri")
+                .short("u")
+                .long("uri")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ca_file")
+                .long("ca_file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("domain")
+                .long("domain")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("no_verify_hostname").long("no_verify_hostname"))
+        .get_matches();
+
+    Args {
+        uri: matches.value_of("uri").unwrap().into(),
+        ca_file: matches.value_of("ca_file").map(|s| s.into()),
+        domain: matches.value_of("domain").map(|s| s.into()),
+        no_verify_hostname: matches.is_present("no_verify_hostname"),
+    }
+}
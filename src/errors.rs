@@ -0,0 +1,324 @@
+//! Errors of amqpr.
+//! Most of errors come from `amqpr_api`. Errors specific to amqpr are wrapped into
+//! `ErrorKind::Io` so that they can be passed through `amqpr_api` functions. Use `find` function
+//! to extract them.
+
+pub use amqpr_api::errors::*;
+
+use std::error::Error as StdError;
+use std::time::Duration;
+use std::fmt;
+use std::io;
+
+use topology::ElementKey;
+
+
+/// Find an amqpr specific error such as `HeartbeatTimeout` inside `Error`.
+pub fn find<T: StdError + 'static>(e: &Error) -> Option<&T> {
+    match e.kind() {
+        &ErrorKind::Io(ref e) => e.get_ref().and_then(|e| e.downcast_ref::<T>()),
+        _ => None,
+    }
+}
+
+
+
+/// No frame arrives from server within two heartbeat intervals.
+/// The connection is considered as dead.
+#[derive(Clone, Debug)]
+pub struct HeartbeatTimeout {
+    pub interval: Duration,
+}
+
+
+impl fmt::Display for HeartbeatTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "No frame is received from server within two heartbeat intervals ({:?})",
+            self.interval
+        )
+    }
+}
+
+
+impl StdError for HeartbeatTimeout {
+    fn description(&self) -> &str {
+        "heartbeat timeout"
+    }
+}
+
+
+impl From<HeartbeatTimeout> for Error {
+    fn from(e: HeartbeatTimeout) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::TimedOut, e))
+    }
+}
+
+
+
+/// Connection is closed or being closed by `GlobalChannel::close`.
+#[derive(Clone, Debug)]
+pub struct ConnectionClosed;
+
+
+impl fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Connection is closed")
+    }
+}
+
+
+impl StdError for ConnectionClosed {
+    fn description(&self) -> &str {
+        "connection closed"
+    }
+}
+
+
+impl From<ConnectionClosed> for Error {
+    fn from(e: ConnectionClosed) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::NotConnected, e))
+    }
+}
+
+
+
+/// Local channel is closed or being closed by `LocalChannel::close`.
+#[derive(Clone, Debug)]
+pub struct ChannelClosed {
+    pub channel_id: u16,
+}
+
+
+impl fmt::Display for ChannelClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Channel {} is closed", self.channel_id)
+    }
+}
+
+
+impl StdError for ChannelClosed {
+    fn description(&self) -> &str {
+        "channel closed"
+    }
+}
+
+
+impl From<ChannelClosed> for Error {
+    fn from(e: ChannelClosed) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::NotConnected, e))
+    }
+}
+
+
+
+/// Channel id can not be used to open a new channel.
+#[derive(Clone, Debug)]
+pub enum ChannelIdError {
+    /// The id is used by another channel which is not closed yet.
+    InUse(u16),
+
+    /// The id is 0 or exceeds `channel_max` negotiated by `Connection.Tune`.
+    OutOfRange { channel_id: u16, channel_max: u16 },
+
+    /// Every id up to `channel_max` is in use.
+    Exhausted { channel_max: u16 },
+}
+
+
+impl fmt::Display for ChannelIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ChannelIdError::InUse(id) => write!(f, "Channel id {} is already in use", id),
+            &ChannelIdError::OutOfRange {
+                channel_id,
+                channel_max,
+            } => {
+                write!(
+                    f,
+                    "Channel id {} is out of range (1 - {})",
+                    channel_id,
+                    channel_max
+                )
+            }
+            &ChannelIdError::Exhausted { channel_max } => {
+                write!(f, "Every channel id up to {} is in use", channel_max)
+            }
+        }
+    }
+}
+
+
+impl StdError for ChannelIdError {
+    fn description(&self) -> &str {
+        "invalid channel id"
+    }
+}
+
+
+impl From<ChannelIdError> for Error {
+    fn from(e: ChannelIdError) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+
+
+/// Channel or connection is closed by server with `Channel.Close` or `Connection.Close`.
+/// amqpr has already replied `Close-Ok` when you get this error.
+#[derive(Clone, Debug)]
+pub struct ClosedByServer {
+    /// 0 if the whole connection is closed.
+    pub channel_id: u16,
+
+    /// Reply code such as 404 (NOT_FOUND) or 406 (PRECONDITION_FAILED).
+    pub reply_code: u16,
+    pub reply_text: String,
+
+    /// Class id and method id of the method which caused this close. 0 if unknown.
+    pub class_id: u16,
+    pub method_id: u16,
+}
+
+
+impl ClosedByServer {
+    /// Returns true if the whole connection is closed rather than a channel.
+    pub fn is_connection(&self) -> bool {
+        self.channel_id == 0
+    }
+}
+
+
+impl fmt::Display for ClosedByServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.is_connection() {
+            true => write!(f, "Connection is closed by server : ")?,
+            false => write!(f, "Channel {} is closed by server : ", self.channel_id)?,
+        }
+        write!(
+            f,
+            "{} {} (class {}, method {})",
+            self.reply_code,
+            self.reply_text,
+            self.class_id,
+            self.method_id
+        )
+    }
+}
+
+
+impl StdError for ClosedByServer {
+    fn description(&self) -> &str {
+        "closed by server"
+    }
+}
+
+
+impl From<ClosedByServer> for Error {
+    fn from(e: ClosedByServer) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::ConnectionAborted, e))
+    }
+}
+
+
+
+/// Reply codes of `Channel.Close` and `Connection.Close` defined by AMQP 0-9-1.
+pub mod reply_code {
+    pub const REPLY_SUCCESS: u16 = 200;
+    pub const CONTENT_TOO_LARGE: u16 = 311;
+    pub const NO_ROUTE: u16 = 312;
+    pub const NO_CONSUMERS: u16 = 313;
+    pub const CONNECTION_FORCED: u16 = 320;
+    pub const INVALID_PATH: u16 = 402;
+    pub const ACCESS_REFUSED: u16 = 403;
+    pub const NOT_FOUND: u16 = 404;
+    pub const RESOURCE_LOCKED: u16 = 405;
+    pub const PRECONDITION_FAILED: u16 = 406;
+    pub const FRAME_ERROR: u16 = 501;
+    pub const SYNTAX_ERROR: u16 = 502;
+    pub const COMMAND_INVALID: u16 = 503;
+    pub const CHANNEL_ERROR: u16 = 504;
+    pub const UNEXPECTED_FRAME: u16 = 505;
+    pub const RESOURCE_ERROR: u16 = 506;
+    pub const NOT_ALLOWED: u16 = 530;
+    pub const NOT_IMPLEMENTED: u16 = 540;
+    pub const INTERNAL_ERROR: u16 = 541;
+}
+
+
+
+/// `Exchange.Delete` with `if_unused` is refused because the exchange still has bindings.
+/// Server closes the channel in this case.
+#[derive(Clone, Debug)]
+pub struct ExchangeInUse {
+    pub exchange: String,
+
+    /// `Channel.Close` sent by server, which is also returned by `cause`.
+    pub closed_by_server: ClosedByServer,
+}
+
+
+impl fmt::Display for ExchangeInUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Exchange {} is in use : {}",
+            self.exchange,
+            self.closed_by_server.reply_text
+        )
+    }
+}
+
+
+impl StdError for ExchangeInUse {
+    fn description(&self) -> &str {
+        "exchange in use"
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        Some(&self.closed_by_server)
+    }
+}
+
+
+impl From<ExchangeInUse> for Error {
+    fn from(e: ExchangeInUse) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+
+
+/// Applying a `Topology` failed at `element`. Server usually closes the channel in this case.
+#[derive(Clone, Debug)]
+pub struct TopologyError {
+    pub element: ElementKey,
+
+    /// Set if server closed the channel or the connection because of `element`.
+    pub closed_by_server: Option<ClosedByServer>,
+
+    /// Description of the underlying error.
+    pub cause: String,
+}
+
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to apply {} : {}", self.element, self.cause)
+    }
+}
+
+
+impl StdError for TopologyError {
+    fn description(&self) -> &str {
+        "failed to apply topology"
+    }
+}
+
+
+impl From<TopologyError> for Error {
+    fn from(e: TopologyError) -> Error {
+        Error::from(io::Error::new(io::ErrorKind::Other, e))
+    }
+}
@@ -1,4 +1,7 @@
 extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_tls;
+extern crate native_tls;
 #[macro_use]
 extern crate futures;
 extern crate ex_futures;
@@ -9,6 +12,16 @@ extern crate log;
 extern crate amqpr_codec;
 extern crate amqpr_api;
 
+#[cfg(feature = "definitions")]
+extern crate serde;
+#[cfg(feature = "definitions")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "definitions")]
+extern crate serde_json;
+#[cfg(feature = "definitions")]
+extern crate toml;
+
 
 pub mod broadcast;
 // pub use broadcast::broadcast_sink;
@@ -18,6 +31,10 @@ pub mod subscribe;
 
 pub mod unsync;
 
-pub mod errors {
-    pub use amqpr_api::errors::*;
-}
+pub mod uri;
+
+pub mod recovery;
+
+pub mod topology;
+
+pub mod errors;
@@ -0,0 +1,662 @@
+//! Automatic connection recovery.
+//!
+//! `Recovery` opens a `LocalChannel` which records topology (exchanges, queues, bindings, QoS and
+//! consumers) declared through it. Every channel opened by the same `Recovery` shares one
+//! connection. A stream or sink created by `Recovery` reconnects with exponential backoff when
+//! the connection fails (including heartbeat timeout), replays the recorded topology on the new
+//! connection and then resumes consuming or publishing transparently.
+//!
+//! ```ignore
+//! let recovery = Recovery::new(uri, RecoveryOption::default(), handle);
+//! let events = recovery.events();
+//!
+//! let declared = recovery
+//!     .open_channel()
+//!     .and_then(|local| local.declare_exchange("logs", ExchangeType::Fanout))
+//!     .and_then(|local| local.declare_anonymous_queue())
+//!     .and_then(|(queue, local)| local.bind_queue(&queue, "logs", "").map(|l| (queue.name, l)));
+//! let stream = recovery.subscribe_stream(declared, "consumer");
+//! ```
+
+mod publish;
+mod subscribe;
+
+pub use self::publish::RecoveringPublishSink;
+pub use self::subscribe::RecoveringSubscribeStream;
+
+use tokio_core::reactor::{Handle, Timeout};
+use futures::{Future, IntoFuture, stream};
+use futures::future::{self, Either, Loop};
+use futures::unsync::mpsc::{self, UnboundedSender, UnboundedReceiver};
+use futures::unsync::oneshot;
+
+use std::cell::RefCell;
+use std::cmp;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+use unsync::{GlobalChannel, LocalChannel, AmqpFuture, BoxedIncome, BoxedOutgo, PublishOption,
+             OpenedChannels, Recorder, Recorded, connect_amqp_uri};
+use uri::AmqpUri;
+use errors::*;
+
+
+pub type RecoveredChannel = LocalChannel<BoxedIncome, BoxedOutgo>;
+
+type Connector = Fn() -> Box<AmqpFuture<GlobalChannel<BoxedIncome, BoxedOutgo>>>;
+
+type Listeners = Rc<RefCell<Vec<UnboundedSender<RecoveryEvent>>>>;
+
+
+
+/// Option of connection recovery.
+#[derive(Clone, Debug)]
+pub struct RecoveryOption {
+    /// Delay before the first reconnection attempt.
+    pub initial_interval: Duration,
+
+    /// Upper limit of delay between reconnection attempts.
+    pub max_interval: Duration,
+
+    /// Delay is multiplied by this value on each failed attempt.
+    pub multiplier: u32,
+
+    /// Give up after this number of failed attempts. `None` means retrying forever.
+    pub max_attempts: Option<usize>,
+}
+
+
+impl Default for RecoveryOption {
+    fn default() -> RecoveryOption {
+        RecoveryOption {
+            initial_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(60),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+}
+
+
+impl RecoveryOption {
+    /// Delay before `attempt`th (0 origin) reconnection attempt.
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let mut delay = self.initial_interval;
+        for _ in 0..attempt {
+            delay = delay * self.multiplier;
+            if delay >= self.max_interval {
+                break;
+            }
+        }
+        cmp::min(delay, self.max_interval)
+    }
+}
+
+
+
+/// Event emitted while recovering connection.
+#[derive(Clone, Debug)]
+pub enum RecoveryEvent {
+    /// Connection is lost.
+    Disconnected(Rc<Error>),
+
+    /// Going to reconnect after `delay`. `attempt` starts from 1.
+    Reconnecting { attempt: usize, delay: Duration },
+
+    /// Connection is recovered and topology is replayed.
+    Reconnected { attempt: usize },
+
+    /// Reached `max_attempts`. Stream or sink will return this error.
+    GaveUp(Rc<Error>),
+}
+
+
+
+/// Factory of recovering streams and sinks.
+/// All streams and sinks created by the same `Recovery` share a connection and recorded topology.
+#[derive(Clone)]
+pub struct Recovery {
+    connector: Rc<Connector>,
+    option: RecoveryOption,
+    recorder: Recorder,
+    listeners: Listeners,
+    shared: Rc<RefCell<Shared>>,
+    handle: Handle,
+}
+
+
+/// Connection shared by every channel opened by a `Recovery`.
+struct Shared {
+    state: SharedState,
+
+    // Number of connections established so far.
+    generation: usize,
+
+    // The latest generation whose loss is notified by `RecoveryEvent::Disconnected`.
+    notified: usize,
+}
+
+
+enum SharedState {
+    /// `None` if not connected yet or the connection is lost.
+    Idle(Option<GlobalChannel<BoxedIncome, BoxedOutgo>>),
+
+    /// Somebody is connecting or opening a channel. Others wait for it.
+    Busy(Vec<oneshot::Sender<()>>),
+}
+
+
+/// Gives the shared connection back when dropped, or forgets it if `global` is not set because
+/// connecting or opening a channel failed.
+struct Release {
+    shared: Rc<RefCell<Shared>>,
+    global: Option<GlobalChannel<BoxedIncome, BoxedOutgo>>,
+    is_new: bool,
+}
+
+
+impl Recovery {
+    /// Create `Recovery` connecting to `uri`.
+    pub fn new(uri: AmqpUri, option: RecoveryOption, handle: Handle) -> Recovery {
+        let handle2 = handle.clone();
+        Recovery::with_connector(move || connect_amqp_uri(&uri, &handle2), option, handle)
+    }
+
+
+    /// Create `Recovery` using arbitrary connector such as `connect_tls`.
+    pub fn with_connector<F>(connector: F, option: RecoveryOption, handle: Handle) -> Recovery
+    where
+        F: Fn() -> Box<AmqpFuture<GlobalChannel<BoxedIncome, BoxedOutgo>>> + 'static,
+    {
+        Recovery {
+            connector: Rc::new(connector),
+            option: option,
+            recorder: Recorder::new(),
+            listeners: Rc::new(RefCell::new(Vec::new())),
+            shared: Rc::new(RefCell::new(Shared {
+                state: SharedState::Idle(None),
+                generation: 0,
+                notified: 0,
+            })),
+            handle: handle,
+        }
+    }
+
+
+    /// Get a stream of `RecoveryEvent`.
+    pub fn events(&self) -> UnboundedReceiver<RecoveryEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.listeners.borrow_mut().push(tx);
+        rx
+    }
+
+
+    /// Returns recorder shared by all channels opened by this `Recovery`.
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+
+    /// Open a recording channel on the shared connection, connecting to AMQP server if there is
+    /// no connection or it is lost. When a new connection is established, topology recorded so
+    /// far is replayed before returned future completes.
+    pub fn open_channel(&self) -> Box<AmqpFuture<RecoveredChannel>> {
+        let this = self.clone();
+        let recorder = self.recorder.clone();
+
+        let fut = self.acquire().and_then(move |(global, mut release)| {
+            let opened = match global {
+                Some(global) => Either::A(global.open_new_channel()),
+                None => {
+                    release.is_new = true;
+                    Either::B(this.connect())
+                }
+            };
+
+            // Replay on a new channel, which does not record yet, so that nothing is recorded
+            // twice.
+            opened.and_then(move |(global, local)| {
+                replay(local, recorder.clone(), release.is_new).map(move |local| {
+                    release.global = Some(global);
+                    local.record(recorder)
+                })
+            })
+        });
+
+        Box::new(fut)
+    }
+
+
+    /// Create a recovering stream of subscribed items.
+    /// `declared` is a future returning queue name and a channel opened by `open_channel`,
+    /// such as the one returned by `declare_private_queue`. Server named queues get new names
+    /// on recovery.
+    pub fn subscribe_stream<F, T>(&self, declared: F, consumer_tag: T) -> RecoveringSubscribeStream
+    where
+        F: Future<Item = (String, RecoveredChannel), Error = Rc<Error>> + 'static,
+        T: Into<String>,
+    {
+        self::subscribe::recovering_subscribe_stream(self.clone(), declared, consumer_tag.into())
+    }
+
+
+    /// Create a recovering sink to publish items.
+    /// `declared` is a future returning a channel opened by `open_channel`.
+    pub fn publish_sink<F>(&self, declared: F, option: PublishOption) -> RecoveringPublishSink
+    where
+        F: Future<Item = RecoveredChannel, Error = Rc<Error>> + 'static,
+    {
+        self::publish::recovering_publish_sink(self.clone(), declared, option)
+    }
+
+
+    /// Reconnect with exponential backoff until it succeeds or reaches `max_attempts`.
+    /// There is no delay if another stream or sink has already reconnected.
+    pub(crate) fn reconnect(&self) -> Box<AmqpFuture<RecoveredChannel>> {
+        let this = self.clone();
+        let fut = future::loop_fn(0, move |attempt| {
+            let delay = match this.is_connected() {
+                true => Duration::from_secs(0),
+                false => this.option.backoff(attempt),
+            };
+            this.emit(RecoveryEvent::Reconnecting {
+                attempt: attempt + 1,
+                delay: delay,
+            });
+
+            let this2 = this.clone();
+            let this3 = this.clone();
+            Timeout::new(delay, &this.handle)
+                .into_future()
+                .flatten()
+                .map_err(|e| Rc::new(Error::from(e)))
+                .and_then(move |()| this2.open_channel())
+                .then(move |res| match res {
+                    Ok(local) => {
+                        info!("Connection is recovered");
+                        this3.emit(RecoveryEvent::Reconnected { attempt: attempt + 1 });
+                        Ok(Loop::Break(local))
+                    }
+                    Err(e) => {
+                        warn!("Fail to recover connection : {:?}", e);
+                        match this3.option.max_attempts {
+                            Some(max) if attempt + 1 >= max => {
+                                this3.emit(RecoveryEvent::GaveUp(e.clone()));
+                                Err(e)
+                            }
+                            _ => Ok(Loop::Continue(attempt + 1)),
+                        }
+                    }
+                })
+        });
+        Box::new(fut)
+    }
+
+
+    /// Called when a stream or sink fails. Unless the shared connection is still open (i.e. only
+    /// the channel failed), it is forgotten and `Disconnected` is emitted once per connection.
+    pub(crate) fn connection_lost(&self, e: Rc<Error>) {
+        let lost = self.shared.borrow_mut().forget(None);
+        if let Some(generation) = lost {
+            notify_lost(&self.shared, &self.listeners, generation, e);
+        }
+    }
+
+
+    pub(crate) fn emit(&self, event: RecoveryEvent) {
+        emit(&self.listeners, event);
+    }
+
+
+    fn is_connected(&self) -> bool {
+        match self.shared.borrow().state {
+            SharedState::Idle(Some(ref global)) => !global.is_closed(),
+            _ => false,
+        }
+    }
+
+
+    /// Take the shared connection, waiting while somebody else uses it.
+    /// Item is `None` if a new connection is required.
+    fn acquire(
+        &self,
+    ) -> Box<AmqpFuture<(Option<GlobalChannel<BoxedIncome, BoxedOutgo>>, Release)>> {
+        let shared = self.shared.clone();
+        let fut = future::loop_fn((), move |()| {
+            let state = mem::replace(
+                &mut shared.borrow_mut().state,
+                SharedState::Busy(Vec::new()),
+            );
+            match state {
+                SharedState::Busy(mut waiters) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    shared.borrow_mut().state = SharedState::Busy(waiters);
+                    Either::A(rx.then(|_| Ok(Loop::Continue(()))))
+                }
+                SharedState::Idle(global) => {
+                    let global = match global {
+                        Some(ref global) if global.is_closed() => None,
+                        global => global,
+                    };
+                    let release = Release {
+                        shared: shared.clone(),
+                        global: None,
+                        is_new: false,
+                    };
+                    Either::B(future::ok(Loop::Break((global, release))))
+                }
+            }
+        });
+        Box::new(fut)
+    }
+
+
+    /// Connect to AMQP server and open the first channel.
+    /// Heartbeat timeout makes the connection lost even if no stream or sink is polled.
+    fn connect(&self) -> Box<AmqpFuture<OpenedChannels>> {
+        let handle = self.handle.clone();
+        let shared = Rc::downgrade(&self.shared);
+        let listeners = self.listeners.clone();
+        let generation = self.shared.borrow().generation + 1;
+
+        let fut = (self.connector)().and_then(move |global| {
+            let (global, heartbeat_error) = global.start_heartbeat(&handle);
+            handle.spawn(heartbeat_error.then(move |res| {
+                // Canceled if heartbeat is disabled or the connection is dropped.
+                if let Ok(e) = res {
+                    warn!("Heartbeat of recovering connection fails : {:?}", e);
+                    watch_heartbeat(shared, listeners, generation, e);
+                }
+                Ok(())
+            }));
+            global.open_new_channel()
+        });
+        Box::new(fut)
+    }
+}
+
+
+impl Shared {
+    /// Forget the connection if it is lost. `generation` is given by heartbeat watcher and
+    /// `None` means the current connection is checked.
+    /// Returns the generation of the lost connection.
+    fn forget(&mut self, generation: Option<usize>) -> Option<usize> {
+        let is_lost = match (&self.state, generation) {
+            (&SharedState::Idle(Some(_)), Some(generation)) => generation == self.generation,
+            (&SharedState::Idle(Some(ref global)), None) => global.is_closed(),
+            (&SharedState::Idle(None), None) => true,
+            (_, Some(generation)) => generation == self.generation,
+
+            // Whoever is using the connection finds out whether it is lost.
+            (&SharedState::Busy(_), None) => false,
+        };
+        if !is_lost {
+            return None;
+        }
+
+        if let SharedState::Idle(Some(_)) = self.state {
+            self.state = SharedState::Idle(None);
+        }
+        Some(self.generation)
+    }
+}
+
+
+impl Drop for Release {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        if self.is_new && self.global.is_some() {
+            shared.generation += 1;
+        }
+        let state = mem::replace(&mut shared.state, SharedState::Idle(self.global.take()));
+        if let SharedState::Busy(waiters) = state {
+            for tx in waiters {
+                let _ = tx.send(());
+            }
+        }
+    }
+}
+
+
+/// Called when heartbeat of the `generation`th connection fails.
+fn watch_heartbeat(
+    shared: Weak<RefCell<Shared>>,
+    listeners: Listeners,
+    generation: usize,
+    e: Rc<Error>,
+) {
+    if let Some(shared) = shared.upgrade() {
+        let lost = shared.borrow_mut().forget(Some(generation));
+        if let Some(generation) = lost {
+            notify_lost(&shared, &listeners, generation, e);
+        }
+    }
+}
+
+
+/// Emit `Disconnected` unless it is already emitted for the connection.
+fn notify_lost(
+    shared: &Rc<RefCell<Shared>>,
+    listeners: &Listeners,
+    generation: usize,
+    e: Rc<Error>,
+) {
+    {
+        let mut shared = shared.borrow_mut();
+        if generation <= shared.notified {
+            return;
+        }
+        shared.notified = generation;
+    }
+    emit(listeners, RecoveryEvent::Disconnected(e));
+}
+
+
+fn emit(listeners: &Listeners, event: RecoveryEvent) {
+    listeners.borrow_mut().retain(|tx| {
+        tx.unbounded_send(event.clone()).is_ok()
+    });
+}
+
+
+/// Replay recorded topology on a new channel.
+/// Server named queues get new names, which are registered to `recorder`.
+fn replay(
+    local: RecoveredChannel,
+    recorder: Recorder,
+    is_new_connection: bool,
+) -> Box<AmqpFuture<RecoveredChannel>> {
+    let records = replay_order(recorder.records(), is_new_connection);
+    let fut = stream::iter_ok::<_, Rc<Error>>(records).fold(local, move |local, record| {
+        let fut: Box<AmqpFuture<RecoveredChannel>> = match record {
+            Recorded::Exchange(option) => local.declare_exchange_with_option(option),
+            Recorded::Queue(option, recorded_name) => {
+                let recorder = recorder.clone();
+                let fut = local.declare_queue_with_option(option).map(move |(name, local)| {
+                    recorder.rename_queue(&recorded_name, name);
+                    local
+                });
+                Box::new(fut)
+            }
+            Recorded::QueueBinding(mut option) => {
+                option.queue = recorder.queue_name(&option.queue);
+                local.bind_queue_with_option(option)
+            }
+            Recorded::ExchangeBinding(option) => local.bind_exchange_with_option(option),
+            Recorded::Qos(option) => local.qos_with_option(option),
+            Recorded::Consumer(_) => Box::new(future::ok(local)),
+        };
+        fut
+    });
+    Box::new(fut)
+}
+
+
+/// Records to replay in the order of replaying.
+/// Declarations are replayed before bindings referring to them. Topology is replayed only on a
+/// new connection while QoS is replayed on every channel. Consumers are left to
+/// `RecoveringSubscribeStream`.
+fn replay_order(records: Vec<Recorded>, is_new_connection: bool) -> Vec<Recorded> {
+    let rank = |record: &Recorded| match record {
+        &Recorded::Qos(_) => Some(0),
+        &Recorded::Exchange(_) if is_new_connection => Some(1),
+        &Recorded::Queue(..) if is_new_connection => Some(2),
+        &Recorded::ExchangeBinding(_) if is_new_connection => Some(3),
+        &Recorded::QueueBinding(_) if is_new_connection => Some(4),
+        _ => None,
+    };
+    let mut ranked: Vec<(usize, Recorded)> = records
+        .into_iter()
+        .filter_map(|record| rank(&record).map(|rank| (rank, record)))
+        .collect();
+
+    // Stable, so that records of the same kind keep the recorded order.
+    ranked.sort_by_key(|&(rank, _)| rank);
+    ranked.into_iter().map(|(_, record)| record).collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unsync::{Arguments, ExchangeType, DeclareExchangeOption, DeclareQueueOption,
+                 BindQueueOption, BindExchangeOption, QosOption, SubscribeOption};
+
+    fn recovery_option(initial: u64, max: u64) -> RecoveryOption {
+        RecoveryOption {
+            initial_interval: Duration::from_secs(initial),
+            max_interval: Duration::from_secs(max),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+
+
+    fn exchange(name: &str) -> Recorded {
+        Recorded::Exchange(DeclareExchangeOption {
+            name: name.into(),
+            typ: ExchangeType::Fanout,
+            is_passive: false,
+            is_durable: false,
+            is_auto_delete: false,
+            is_internal: false,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        })
+    }
+
+
+    fn queue(name: &str) -> Recorded {
+        let option = DeclareQueueOption {
+            name: name.into(),
+            is_passive: false,
+            is_durable: false,
+            is_exclusive: false,
+            is_auto_delete: false,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+        Recorded::Queue(option, name.into())
+    }
+
+
+    fn queue_binding(queue: &str, exchange: &str) -> Recorded {
+        Recorded::QueueBinding(BindQueueOption {
+            queue: queue.into(),
+            exchange: exchange.into(),
+            routing_key: "".into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        })
+    }
+
+
+    fn exchange_binding(destination: &str, source: &str) -> Recorded {
+        Recorded::ExchangeBinding(BindExchangeOption {
+            destination: destination.into(),
+            source: source.into(),
+            routing_key: "".into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        })
+    }
+
+
+    fn qos() -> Recorded {
+        Recorded::Qos(QosOption {
+            prefetch_size: 0,
+            prefetch_count: 10,
+            is_global: false,
+        })
+    }
+
+
+    fn consumer(queue: &str) -> Recorded {
+        Recorded::Consumer(SubscribeOption {
+            queue: queue.into(),
+            consumer_tag: "consumer".into(),
+            is_no_local: false,
+            is_exclusive: true,
+            arguments: Arguments::new(),
+        })
+    }
+
+
+    #[test]
+    fn backoff_exponentially() {
+        let option = recovery_option(1, 60);
+        let delays: Vec<u64> = (0..4).map(|attempt| option.backoff(attempt).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8]);
+    }
+
+
+    #[test]
+    fn backoff_up_to_max_interval() {
+        let option = recovery_option(1, 60);
+        assert_eq!(option.backoff(6), Duration::from_secs(60));
+        assert_eq!(option.backoff(1000), Duration::from_secs(60));
+
+        let option = recovery_option(90, 60);
+        assert_eq!(option.backoff(0), Duration::from_secs(60));
+    }
+
+
+    #[test]
+    fn replay_declarations_before_bindings() {
+        let records = vec![
+            exchange("logs"),
+            queue("q1"),
+            queue_binding("q1", "logs"),
+            consumer("q1"),
+            exchange("audit"),
+            exchange_binding("audit", "logs"),
+            qos(),
+            queue("q2"),
+            queue_binding("q2", "audit"),
+        ];
+        let replayed = replay_order(records, true);
+        assert_eq!(
+            replayed,
+            vec![
+                qos(),
+                exchange("logs"),
+                exchange("audit"),
+                queue("q1"),
+                queue("q2"),
+                exchange_binding("audit", "logs"),
+                queue_binding("q1", "logs"),
+                queue_binding("q2", "audit"),
+            ]
+        );
+    }
+
+
+    #[test]
+    fn replay_only_qos_on_existing_connection() {
+        let records = vec![exchange("logs"), queue("q"), qos(), consumer("q")];
+        assert_eq!(replay_order(records, false), vec![qos()]);
+    }
+}
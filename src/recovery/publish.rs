@@ -0,0 +1,198 @@
+use futures::{Future, Sink, Poll, StartSend, Async, AsyncSink};
+
+use ex_futures::sink::UnsyncCloneable;
+
+use bytes::Bytes;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use unsync::{AmqpFuture, BoxedOutgo, PublishSink, PublishOption, DEFAULT_HIGH_WATER_MARK};
+use errors::*;
+use super::{Recovery, RecoveredChannel};
+
+
+pub fn recovering_publish_sink<F>(
+    recovery: Recovery,
+    declared: F,
+    option: PublishOption,
+) -> RecoveringPublishSink
+where
+    F: Future<Item = RecoveredChannel, Error = Rc<Error>> + 'static,
+{
+    RecoveringPublishSink {
+        recovery: recovery,
+        option: option,
+        state: State::Declaring(Box::new(declared)),
+        unflushed: VecDeque::new(),
+        unflushed_bytes: 0,
+        sent: 0,
+        high_water_mark: DEFAULT_HIGH_WATER_MARK,
+    }
+}
+
+
+/// Sink to publish items which survives connection failure.
+/// When connection is lost, it reconnects, replays recorded topology and publishes again every
+/// item which was not flushed yet. So an item may be published twice.
+///
+/// Like `PublishSink`, items are not flushed until `poll_complete` is called. Unflushed items are
+/// kept for recovery, so `start_send` flushes them before accepting a new item once their bytes
+/// exceed the high-water mark.
+pub struct RecoveringPublishSink {
+    recovery: Recovery,
+    option: PublishOption,
+    state: State,
+
+    // Items which are accepted but not flushed yet.
+    unflushed: VecDeque<Bytes>,
+    unflushed_bytes: usize,
+
+    // Number of items in `unflushed` which the current `PublishSink` has accepted.
+    // It may return `NotReady` while connection is blocked.
+    sent: usize,
+
+    high_water_mark: usize,
+}
+
+
+enum State {
+    Declaring(Box<AmqpFuture<RecoveredChannel>>),
+    Recovering(Box<AmqpFuture<RecoveredChannel>>),
+    Publishing(PublishSink<UnsyncCloneable<BoxedOutgo>>),
+}
+
+
+impl RecoveringPublishSink {
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+
+    /// Set the number of unflushed bytes above which `start_send` flushes them before accepting
+    /// a new item. It is also set to `PublishSink` inside.
+    pub fn set_high_water_mark(&mut self, bytes: usize) {
+        self.high_water_mark = bytes;
+        if let State::Publishing(ref mut sink) = self.state {
+            sink.set_high_water_mark(bytes);
+        }
+    }
+
+
+    fn publish(&mut self, local: RecoveredChannel) -> State {
+        let (_local, mut sink) = local.publish_sink_with_option(self.option.clone());
+        sink.set_high_water_mark(self.high_water_mark);
+
+        // Every unflushed item is sent again by `poll_publishing`.
+        self.sent = 0;
+        State::Publishing(sink)
+    }
+
+
+    fn recover(&mut self, e: Rc<Error>) -> State {
+        warn!("Publishing connection is lost : {:?}", e);
+        self.recovery.connection_lost(e);
+        State::Recovering(self.recovery.reconnect())
+    }
+
+
+    /// `Ready` when a `PublishSink` is available and it has accepted every unflushed item.
+    fn poll_publishing(&mut self) -> Poll<(), Rc<Error>> {
+        use self::State::*;
+
+        loop {
+            let res = match &mut self.state {
+                &mut Declaring(ref mut fut) => {
+                    // An error before the first publish is not recovered.
+                    let local = try_ready!(fut.poll());
+                    Ok(local)
+                }
+                &mut Recovering(ref mut fut) => {
+                    let local = try_ready!(fut.poll());
+                    Ok(local)
+                }
+                &mut Publishing(ref mut sink) => {
+                    let mut res = Ok(Async::Ready(()));
+                    while self.sent < self.unflushed.len() {
+                        match sink.start_send(self.unflushed[self.sent].clone()) {
+                            Ok(AsyncSink::Ready) => self.sent += 1,
+                            // Keep items until connection is unblocked.
+                            Ok(AsyncSink::NotReady(_)) => {
+                                res = Ok(Async::NotReady);
+                                break;
+                            }
+                            Err(e) => {
+                                res = Err(e);
+                                break;
+                            }
+                        }
+                    }
+                    match res {
+                        Ok(ready) => return Ok(ready),
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+
+            self.state = match res {
+                Ok(local) => self.publish(local),
+                Err(e) => self.recover(e),
+            };
+        }
+    }
+}
+
+
+impl Sink for RecoveringPublishSink {
+    type SinkItem = Bytes;
+    type SinkError = Rc<Error>;
+
+    fn start_send(&mut self, bytes: Bytes) -> StartSend<Bytes, Rc<Error>> {
+        if self.unflushed_bytes > self.high_water_mark {
+            if let Async::NotReady = self.poll_complete()? {
+                return Ok(AsyncSink::NotReady(bytes));
+            }
+        }
+        if let Async::NotReady = self.poll_publishing()? {
+            return Ok(AsyncSink::NotReady(bytes));
+        }
+
+        let res = match &mut self.state {
+            &mut State::Publishing(ref mut sink) => sink.start_send(bytes.clone()),
+            _ => unreachable!(),
+        };
+
+        match res {
+            Ok(AsyncSink::Ready) => self.sent += 1,
+            Ok(AsyncSink::NotReady(bytes)) => return Ok(AsyncSink::NotReady(bytes)),
+            Err(e) => self.state = self.recover(e),
+        }
+        self.unflushed_bytes += bytes.len();
+        self.unflushed.push_back(bytes);
+
+        Ok(AsyncSink::Ready)
+    }
+
+
+    fn poll_complete(&mut self) -> Poll<(), Rc<Error>> {
+        loop {
+            try_ready!(self.poll_publishing());
+
+            let res = match &mut self.state {
+                &mut State::Publishing(ref mut sink) => sink.poll_complete(),
+                _ => unreachable!(),
+            };
+
+            match res {
+                Ok(Async::Ready(())) => {
+                    self.unflushed.clear();
+                    self.unflushed_bytes = 0;
+                    self.sent = 0;
+                    return Ok(Async::Ready(()));
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => self.state = self.recover(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use futures::{Future, Stream, Poll, Async};
+
+use ex_futures::sink::UnsyncCloneable;
+
+use bytes::Bytes;
+
+use std::rc::Rc;
+
+use unsync::{AmqpFuture, BoxedIncome, BoxedOutgo};
+use unsync::SubscribeStream;
+use errors::*;
+use super::{Recovery, RecoveredChannel};
+
+
+type DeclaredFuture = Box<Future<Item = (String, RecoveredChannel), Error = Rc<Error>>>;
+
+
+pub fn recovering_subscribe_stream<F>(
+    recovery: Recovery,
+    declared: F,
+    consumer_tag: String,
+) -> RecoveringSubscribeStream
+where
+    F: Future<Item = (String, RecoveredChannel), Error = Rc<Error>> + 'static,
+{
+    RecoveringSubscribeStream {
+        recovery: recovery,
+        queue: String::new(),
+        consumer_tag: consumer_tag,
+        state: State::Declaring(Box::new(declared)),
+    }
+}
+
+
+/// Stream of subscribed items which survives connection failure.
+/// When connection is lost, it reconnects, replays recorded topology and starts consuming the
+/// same queue again. Items delivered but not yet received at that time are lost.
+/// It ends without recovery when the channel or the connection is closed by client.
+pub struct RecoveringSubscribeStream {
+    recovery: Recovery,
+
+    // Name of the queue at the first declaration.
+    queue: String,
+    consumer_tag: String,
+    state: State,
+}
+
+
+enum State {
+    Declaring(DeclaredFuture),
+    Recovering(Box<AmqpFuture<RecoveredChannel>>),
+    Subscribing(SubscribeStream<BoxedIncome, UnsyncCloneable<BoxedOutgo>>),
+}
+
+enum Next {
+    Declared(String, RecoveredChannel),
+    Recovered(RecoveredChannel),
+    Lost(Rc<Error>),
+}
+
+
+impl RecoveringSubscribeStream {
+    fn subscribe(&mut self, local: RecoveredChannel) -> State {
+        // The consumer is recorded at the first subscription. Queue names in the record are the
+        // ones at the first declaration.
+        let recorder = self.recovery.recorder().clone();
+        let (_local, stream) = match recorder.consumer(&self.consumer_tag) {
+            Some(mut option) => {
+                option.queue = recorder.queue_name(&option.queue);
+                local.subscribe_stream_with_option(option)
+            }
+            None => {
+                let queue = recorder.queue_name(&self.queue);
+                local.subscribe_stream(queue, self.consumer_tag.as_str())
+            }
+        };
+        State::Subscribing(stream)
+    }
+
+
+    fn recover(&mut self, e: Rc<Error>) -> State {
+        warn!("Subscribing connection is lost : {:?}", e);
+        self.recovery.connection_lost(e);
+        State::Recovering(self.recovery.reconnect())
+    }
+}
+
+
+impl Stream for RecoveringSubscribeStream {
+    type Item = Bytes;
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Rc<Error>> {
+        use self::State::*;
+
+        let next = match &mut self.state {
+            &mut Declaring(ref mut fut) => {
+                // An error before the first subscription is not recovered.
+                let (queue, local) = try_ready!(fut.poll());
+                Next::Declared(queue, local)
+            }
+            &mut Recovering(ref mut fut) => Next::Recovered(try_ready!(fut.poll())),
+            &mut Subscribing(ref mut stream) => {
+                match stream.poll() {
+                    Ok(Async::Ready(Some(bytes))) => return Ok(Async::Ready(Some(bytes))),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // The channel or the connection is closed locally or gracefully. Unexpected
+                    // EOF of the socket is reported as an error by the reader.
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                    Err(e) => Next::Lost(e),
+                }
+            }
+        };
+
+        self.state = match next {
+            Next::Declared(queue, local) => {
+                self.queue = queue;
+                self.subscribe(local)
+            }
+            Next::Recovered(local) => self.subscribe(local),
+            Next::Lost(e) => self.recover(e),
+        };
+
+        self.poll()
+    }
+}
@@ -0,0 +1,518 @@
+//! Load `Topology` from a definitions document.
+//!
+//! The shape follows `definitions.json` exported by RabbitMQ management plugin, so an exported
+//! file can be used as it is. Other sections such as `users` and `policies` are ignored.
+//!
+//! ```json
+//! {
+//!   "exchanges": [
+//!     { "name": "logs", "type": "topic", "durable": true, "arguments": {} }
+//!   ],
+//!   "queues": [
+//!     { "name": "errors", "durable": true, "arguments": { "x-message-ttl": 60000 } }
+//!   ],
+//!   "bindings": [
+//!     { "source": "logs", "destination": "errors", "destination_type": "queue",
+//!       "routing_key": "*.error" }
+//!   ]
+//! }
+//! ```
+//!
+//! TOML document has the same structure.
+//!
+//! ```toml
+//! [[exchanges]]
+//! name = "logs"
+//! type = "topic"
+//!
+//! [[queues]]
+//! name = "errors"
+//! arguments = { x-message-ttl = 60000 }
+//!
+//! [[bindings]]
+//! source = "logs"
+//! destination = "errors"
+//! routing_key = "*.error"
+//! ```
+//!
+//! Argument values may be booleans, strings, integers, floats, arrays and tables. They are
+//! mapped to `Boolean`, `LongString`, `LongLongInt`, `Double`, `FieldArray` and `FieldTable`.
+//!
+//! A document exported from a broker with many vhosts has elements of all of them. `from_json`
+//! and `from_toml` refuse such a document; use `from_json_in_vhost` or `from_toml_in_vhost` to
+//! pick one. Elements without `vhost` field belong to every vhost.
+
+use amqpr_codec::args::FieldArgument;
+
+use serde_json::{self, Value};
+use toml;
+
+use std::collections::{HashMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+use unsync::{Arguments, ExchangeType, DeclareExchangeOption, DeclareQueueOption, BindQueueOption,
+             BindExchangeOption};
+use super::Topology;
+use errors::*;
+
+
+#[derive(Deserialize, Debug)]
+struct Definitions {
+    #[serde(default)]
+    exchanges: Vec<ExchangeDefinition>,
+
+    #[serde(default)]
+    queues: Vec<QueueDefinition>,
+
+    #[serde(default)]
+    bindings: Vec<BindingDefinition>,
+}
+
+
+#[derive(Deserialize, Debug)]
+struct ExchangeDefinition {
+    name: String,
+
+    #[serde(default)]
+    vhost: Option<String>,
+
+    #[serde(rename = "type")]
+    typ: String,
+
+    #[serde(default = "default_true")]
+    durable: bool,
+
+    #[serde(default)]
+    auto_delete: bool,
+
+    #[serde(default)]
+    internal: bool,
+
+    #[serde(default)]
+    arguments: HashMap<String, Value>,
+}
+
+
+#[derive(Deserialize, Debug)]
+struct QueueDefinition {
+    name: String,
+
+    #[serde(default)]
+    vhost: Option<String>,
+
+    #[serde(default = "default_true")]
+    durable: bool,
+
+    #[serde(default)]
+    exclusive: bool,
+
+    #[serde(default)]
+    auto_delete: bool,
+
+    #[serde(default)]
+    arguments: HashMap<String, Value>,
+}
+
+
+#[derive(Deserialize, Debug)]
+struct BindingDefinition {
+    source: String,
+    destination: String,
+
+    #[serde(default)]
+    vhost: Option<String>,
+
+    /// "queue" or "exchange".
+    #[serde(default = "default_destination_type")]
+    destination_type: String,
+
+    #[serde(default)]
+    routing_key: String,
+
+    #[serde(default)]
+    arguments: HashMap<String, Value>,
+}
+
+
+fn default_true() -> bool {
+    true
+}
+
+
+fn default_destination_type() -> String {
+    "queue".into()
+}
+
+
+
+impl Topology {
+    /// Parse a JSON definitions document.
+    /// `durable` is true if it is omitted, as in RabbitMQ management plugin.
+    /// Fails if the document has elements of more than one vhost.
+    pub fn from_json(s: &str) -> Result<Topology, Rc<Error>> {
+        let definitions = serde_json::from_str::<Definitions>(s).map_err(invalid_data)?;
+        definitions.into_topology(None)
+    }
+
+
+    /// Parse a JSON definitions document and take elements of `vhost` only.
+    pub fn from_json_in_vhost(s: &str, vhost: &str) -> Result<Topology, Rc<Error>> {
+        let definitions = serde_json::from_str::<Definitions>(s).map_err(invalid_data)?;
+        definitions.into_topology(Some(vhost))
+    }
+
+
+    /// Parse a TOML definitions document.
+    /// Fails if the document has elements of more than one vhost.
+    pub fn from_toml(s: &str) -> Result<Topology, Rc<Error>> {
+        let definitions = toml::from_str::<Definitions>(s).map_err(invalid_data)?;
+        definitions.into_topology(None)
+    }
+
+
+    /// Parse a TOML definitions document and take elements of `vhost` only.
+    pub fn from_toml_in_vhost(s: &str, vhost: &str) -> Result<Topology, Rc<Error>> {
+        let definitions = toml::from_str::<Definitions>(s).map_err(invalid_data)?;
+        definitions.into_topology(Some(vhost))
+    }
+
+
+    /// Read a definitions file. It is parsed as TOML if its extension is "toml", otherwise as
+    /// JSON.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Topology, Rc<Error>> {
+        read_definitions(path.as_ref())?.into_topology(None)
+    }
+
+
+    /// Same with `load` but takes elements of `vhost` only.
+    pub fn load_in_vhost<P: AsRef<Path>>(path: P, vhost: &str) -> Result<Topology, Rc<Error>> {
+        read_definitions(path.as_ref())?.into_topology(Some(vhost))
+    }
+}
+
+
+fn read_definitions(path: &Path) -> Result<Definitions, Rc<Error>> {
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .map_err(|e| Rc::new(Error::from(e)))?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(invalid_data),
+        _ => serde_json::from_str(&content).map_err(invalid_data),
+    }
+}
+
+
+impl Definitions {
+    /// Take elements of `vhost`, or check that there is only one vhost if `vhost` is `None`.
+    fn into_topology(mut self, vhost: Option<&str>) -> Result<Topology, Rc<Error>> {
+        match vhost {
+            Some(vhost) => self.retain_vhost(vhost),
+            None => self.check_single_vhost()?,
+        }
+
+        let mut topology = Topology::new();
+
+        for exchange in self.exchanges {
+            topology = topology.declare_exchange(DeclareExchangeOption {
+                typ: ExchangeType::from(exchange.typ.as_str()),
+                name: exchange.name,
+                is_passive: false,
+                is_durable: exchange.durable,
+                is_auto_delete: exchange.auto_delete,
+                is_internal: exchange.internal,
+                is_no_wait: false,
+                arguments: arguments(exchange.arguments)?,
+            });
+        }
+
+        for queue in self.queues {
+            topology = topology.declare_queue(DeclareQueueOption {
+                name: queue.name,
+                is_passive: false,
+                is_durable: queue.durable,
+                is_exclusive: queue.exclusive,
+                is_auto_delete: queue.auto_delete,
+                is_no_wait: false,
+                arguments: arguments(queue.arguments)?,
+            });
+        }
+
+        for binding in self.bindings {
+            topology = match binding.destination_type.as_str() {
+                "queue" => topology.bind_queue(BindQueueOption {
+                    queue: binding.destination,
+                    exchange: binding.source,
+                    routing_key: binding.routing_key,
+                    is_no_wait: false,
+                    arguments: arguments(binding.arguments)?,
+                }),
+                "exchange" => topology.bind_exchange(BindExchangeOption {
+                    destination: binding.destination,
+                    source: binding.source,
+                    routing_key: binding.routing_key,
+                    is_no_wait: false,
+                    arguments: arguments(binding.arguments)?,
+                }),
+                other => {
+                    let msg = format!("Unknown destination_type of binding : {}", other);
+                    return Err(invalid_data(msg));
+                }
+            };
+        }
+
+        Ok(topology)
+    }
+
+
+    fn retain_vhost(&mut self, vhost: &str) {
+        self.exchanges.retain(|e| in_vhost(&e.vhost, vhost));
+        self.queues.retain(|q| in_vhost(&q.vhost, vhost));
+        self.bindings.retain(|b| in_vhost(&b.vhost, vhost));
+    }
+
+
+    fn check_single_vhost(&self) -> Result<(), Rc<Error>> {
+        let vhosts: BTreeSet<&str> = self.exchanges
+            .iter()
+            .map(|e| &e.vhost)
+            .chain(self.queues.iter().map(|q| &q.vhost))
+            .chain(self.bindings.iter().map(|b| &b.vhost))
+            .filter_map(|vhost| vhost.as_ref().map(|v| v.as_str()))
+            .collect();
+
+        if vhosts.len() > 1 {
+            let vhosts: Vec<_> = vhosts.into_iter().collect();
+            let msg = format!(
+                "Definitions have elements of multiple vhosts ({}). Choose one of them",
+                vhosts.join(", ")
+            );
+            return Err(invalid_data(msg));
+        }
+        Ok(())
+    }
+}
+
+
+fn in_vhost(element: &Option<String>, vhost: &str) -> bool {
+    match *element {
+        Some(ref v) => v == vhost,
+        None => true,
+    }
+}
+
+
+fn arguments(table: HashMap<String, Value>) -> Result<Arguments, Rc<Error>> {
+    Ok(Arguments::from(field_table(table)?))
+}
+
+
+fn field_table(table: HashMap<String, Value>) -> Result<HashMap<String, FieldArgument>, Rc<Error>> {
+    let mut converted = HashMap::new();
+    for (key, value) in table {
+        let value = field_argument(&key, value)?;
+        converted.insert(key, value);
+    }
+    Ok(converted)
+}
+
+
+fn field_argument(key: &str, value: Value) -> Result<FieldArgument, Rc<Error>> {
+    match value {
+        Value::Bool(b) => Ok(FieldArgument::Boolean(b)),
+        Value::String(s) => Ok(FieldArgument::LongString(s)),
+        Value::Number(ref n) if n.is_i64() => Ok(FieldArgument::LongLongInt(n.as_i64().unwrap())),
+        Value::Number(ref n) if n.is_f64() => Ok(FieldArgument::Double(n.as_f64().unwrap())),
+        Value::Array(values) => {
+            let values = values
+                .into_iter()
+                .map(|value| field_argument(key, value))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(FieldArgument::FieldArray(values))
+        }
+        Value::Object(map) => {
+            let table = field_table(map.into_iter().collect())?;
+            Ok(FieldArgument::FieldTable(table))
+        }
+        value => {
+            let msg = format!("Unsupported value of argument {} : {}", key, value);
+            Err(invalid_data(msg))
+        }
+    }
+}
+
+
+fn invalid_data<E>(e: E) -> Rc<Error>
+where
+    E: Into<Box<::std::error::Error + Send + Sync>>,
+{
+    Rc::new(Error::from(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Output of `rabbitmqctl export_definitions` (RabbitMQ 3.12) trimmed of users' hashes.
+    const EXPORTED: &str = r#"{
+        "rabbit_version": "3.12.4",
+        "rabbitmq_version": "3.12.4",
+        "product_name": "RabbitMQ",
+        "product_version": "3.12.4",
+        "users": [
+            {"name": "guest", "password_hash": "", "hashing_algorithm":
+             "rabbit_password_hashing_sha256", "tags": ["administrator"], "limits": {}}
+        ],
+        "vhosts": [{"name": "/"}],
+        "permissions": [
+            {"user": "guest", "vhost": "/", "configure": ".*", "write": ".*", "read": ".*"}
+        ],
+        "topic_permissions": [],
+        "parameters": [],
+        "global_parameters": [
+            {"name": "internal_cluster_id", "value": "rabbitmq-cluster-id-3mWR2qgE1Wa1I"}
+        ],
+        "policies": [],
+        "queues": [
+            {"name": "orders", "vhost": "/", "durable": true, "auto_delete": false,
+             "arguments": {"x-queue-type": "quorum", "x-max-length": 10000}}
+        ],
+        "exchanges": [
+            {"name": "events", "vhost": "/", "type": "topic", "durable": true,
+             "auto_delete": false, "internal": false, "arguments": {}},
+            {"name": "documents", "vhost": "/", "type": "headers", "durable": true,
+             "auto_delete": false, "internal": false, "arguments": {}}
+        ],
+        "bindings": [
+            {"source": "events", "vhost": "/", "destination": "orders",
+             "destination_type": "queue", "routing_key": "order.*", "arguments": {}},
+            {"source": "events", "vhost": "/", "destination": "documents",
+             "destination_type": "exchange", "routing_key": "document.#",
+             "arguments": {"formats": ["pdf", "zip"], "x-match": "any", "weight": 0.5}}
+        ]
+    }"#;
+
+    const EXPORTED_TOML: &str = r#"
+        [[queues]]
+        name = "orders"
+        vhost = "/"
+        arguments = { x-queue-type = "quorum", x-max-length = 10000 }
+
+        [[exchanges]]
+        name = "events"
+        vhost = "/"
+        type = "topic"
+
+        [[exchanges]]
+        name = "documents"
+        vhost = "/"
+        type = "headers"
+
+        [[bindings]]
+        source = "events"
+        vhost = "/"
+        destination = "orders"
+        routing_key = "order.*"
+
+        [[bindings]]
+        source = "events"
+        vhost = "/"
+        destination = "documents"
+        destination_type = "exchange"
+        routing_key = "document.#"
+        arguments = { formats = ["pdf", "zip"], x-match = "any", weight = 0.5 }
+    "#;
+
+    const MULTI_VHOST: &str = r#"{
+        "queues": [
+            {"name": "orders", "vhost": "/", "durable": true, "arguments": {}},
+            {"name": "orders", "vhost": "staging", "durable": false, "arguments": {}},
+            {"name": "audit", "durable": true, "arguments": {}}
+        ]
+    }"#;
+
+
+    #[test]
+    fn load_exported_json() {
+        let topology = Topology::from_json(EXPORTED).unwrap();
+
+        let exchanges: Vec<_> = topology.exchanges.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(exchanges, vec!["events", "documents"]);
+        assert_eq!(topology.exchanges[0].typ, ExchangeType::Topic);
+        assert_eq!(topology.exchanges[1].typ, ExchangeType::Headers);
+        assert!(topology.exchanges[0].is_durable);
+
+        assert_eq!(topology.queues.len(), 1);
+        let orders = &topology.queues[0];
+        assert_eq!(orders.name, "orders");
+        assert!(orders.is_durable);
+        assert_eq!(
+            orders.arguments.get("x-queue-type"),
+            Some(&FieldArgument::LongString("quorum".into()))
+        );
+        assert_eq!(
+            orders.arguments.get("x-max-length"),
+            Some(&FieldArgument::LongLongInt(10000))
+        );
+
+        assert_eq!(topology.queue_bindings.len(), 1);
+        assert_eq!(topology.queue_bindings[0].queue, "orders");
+        assert_eq!(topology.queue_bindings[0].exchange, "events");
+        assert_eq!(topology.queue_bindings[0].routing_key, "order.*");
+
+        assert_eq!(topology.exchange_bindings.len(), 1);
+        let binding = &topology.exchange_bindings[0];
+        assert_eq!(binding.destination, "documents");
+        assert_eq!(binding.source, "events");
+        let formats = vec![
+            FieldArgument::LongString("pdf".into()),
+            FieldArgument::LongString("zip".into()),
+        ];
+        assert_eq!(binding.arguments.get("formats"), Some(&FieldArgument::FieldArray(formats)));
+        assert_eq!(binding.arguments.get("weight"), Some(&FieldArgument::Double(0.5)));
+    }
+
+
+    #[test]
+    fn toml_is_same_with_json() {
+        let json = Topology::from_json(EXPORTED).unwrap();
+        let toml = Topology::from_toml(EXPORTED_TOML).unwrap();
+        assert_eq!(json, toml);
+    }
+
+
+    #[test]
+    fn reject_multiple_vhosts() {
+        let e = Topology::from_json(MULTI_VHOST).unwrap_err();
+        assert!(e.to_string().contains("/, staging"), "{}", e);
+    }
+
+
+    #[test]
+    fn take_elements_of_vhost() {
+        let topology = Topology::from_json_in_vhost(MULTI_VHOST, "staging").unwrap();
+        let queues: Vec<_> = topology
+            .queues
+            .iter()
+            .map(|q| (q.name.as_str(), q.is_durable))
+            .collect();
+        assert_eq!(queues, vec![("orders", false), ("audit", true)]);
+    }
+
+
+    #[test]
+    fn reject_unknown_destination_type_and_null() {
+        let json = r#"{"bindings": [{"source": "a", "destination": "b",
+                                     "destination_type": "topic"}]}"#;
+        assert!(Topology::from_json(json).is_err());
+
+        let json = r#"{"queues": [{"name": "a", "arguments": {"x-expires": null}}]}"#;
+        assert!(Topology::from_json(json).is_err());
+    }
+}
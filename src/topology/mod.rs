@@ -0,0 +1,592 @@
+//! Declarative description of exchanges, queues and bindings.
+//!
+//! `Topology` is applied to a `LocalChannel` in dependency order (exchanges, queues, exchange
+//! bindings and then queue bindings). If an element fails, returned future fails with
+//! `TopologyError` which tells you the element.
+//!
+//! ```ignore
+//! let topology = Topology::new()
+//!     .declare_exchange(logs_exchange_option)
+//!     .declare_queue(logs_queue_option)
+//!     .bind_queue(logs_binding_option);
+//!
+//! let fut = topology.apply(local);
+//! ```
+//!
+//! When you change the topology later, `diff` tells you what should be declared and removed.
+//! By default, removed exchanges and queues are deleted only if they are unused, so that
+//! messages are not lost by a mistake in configuration.
+//!
+//! With `definitions` feature, `Topology` can be loaded from JSON or TOML document. See
+//! `definitions` module.
+
+#[cfg(feature = "definitions")]
+pub mod definitions;
+
+use futures::{Future, Stream, stream};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use unsync::{LocalChannel, AmqpFuture, Income, Outgo, DeclareExchangeOption, DeclareQueueOption,
+             BindQueueOption, BindExchangeOption, DeleteQueueOption, UnbindQueueOption};
+use errors::*;
+
+
+pub type TopologyFuture<In, Out> = Box<AmqpFuture<LocalChannel<In, Out>>>;
+
+
+/// Set of exchanges, queues and bindings.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Topology {
+    pub exchanges: Vec<DeclareExchangeOption>,
+    pub queues: Vec<DeclareQueueOption>,
+    pub exchange_bindings: Vec<BindExchangeOption>,
+    pub queue_bindings: Vec<BindQueueOption>,
+}
+
+
+/// An element of `Topology`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Element {
+    Exchange(DeclareExchangeOption),
+    Queue(DeclareQueueOption),
+    ExchangeBinding(BindExchangeOption),
+    QueueBinding(BindQueueOption),
+}
+
+
+/// Identity of an `Element`. Two elements with the same key refer to the same object on server.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ElementKey {
+    Exchange(String),
+    Queue(String),
+    ExchangeBinding {
+        destination: String,
+        source: String,
+        routing_key: String,
+    },
+    QueueBinding {
+        queue: String,
+        exchange: String,
+        routing_key: String,
+    },
+}
+
+
+/// Difference between two topologies.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TopologyDiff {
+    /// Elements which exist only in the new topology, in dependency order.
+    pub added: Vec<Element>,
+
+    /// Elements which exist only in the previous topology, in reverse dependency order.
+    pub removed: Vec<Element>,
+
+    /// Elements which exist in both topologies with different options, as (previous, new).
+    pub changed: Vec<(Element, Element)>,
+}
+
+
+/// How `TopologyDiff` deletes removed exchanges and queues.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Removal {
+    /// Delete an exchange only if it has no bindings, and a queue only if it has neither
+    /// messages nor consumers. Otherwise applying fails with `TopologyError`
+    /// (406 PRECONDITION_FAILED).
+    IfUnused,
+
+    /// Delete them unconditionally. Messages in removed queues are lost and their consumers
+    /// are cancelled.
+    Force,
+}
+
+
+
+impl Topology {
+    pub fn new() -> Topology {
+        Topology::default()
+    }
+
+
+    pub fn declare_exchange(mut self, option: DeclareExchangeOption) -> Topology {
+        self.exchanges.push(option);
+        self
+    }
+
+
+    pub fn declare_queue(mut self, option: DeclareQueueOption) -> Topology {
+        self.queues.push(option);
+        self
+    }
+
+
+    pub fn bind_exchange(mut self, option: BindExchangeOption) -> Topology {
+        self.exchange_bindings.push(option);
+        self
+    }
+
+
+    pub fn bind_queue(mut self, option: BindQueueOption) -> Topology {
+        self.queue_bindings.push(option);
+        self
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.exchanges.is_empty() && self.queues.is_empty() &&
+            self.exchange_bindings.is_empty() && self.queue_bindings.is_empty()
+    }
+
+
+    /// All elements in dependency order.
+    pub fn elements(&self) -> Vec<Element> {
+        let exchanges = self.exchanges.iter().cloned().map(Element::Exchange);
+        let queues = self.queues.iter().cloned().map(Element::Queue);
+        let exchange_bindings = self.exchange_bindings.iter().cloned().map(
+            Element::ExchangeBinding,
+        );
+        let queue_bindings = self.queue_bindings.iter().cloned().map(Element::QueueBinding);
+        exchanges
+            .chain(queues)
+            .chain(exchange_bindings)
+            .chain(queue_bindings)
+            .collect()
+    }
+
+
+    /// Declare every element on `local` one by one.
+    /// Queues should be named because bindings refer to them by name.
+    pub fn apply<In, Out>(&self, local: LocalChannel<In, Out>) -> TopologyFuture<In, Out>
+    where
+        In: Income,
+        Out: Outgo,
+    {
+        let steps = self.elements().into_iter().map(Step::Declare).collect();
+        run(local, steps, Removal::IfUnused)
+    }
+
+
+    /// Compute what is changed from `previous` to `self`.
+    pub fn diff(&self, previous: &Topology) -> TopologyDiff {
+        let previous_elements = previous.elements();
+        let previous_map: HashMap<ElementKey, &Element> = previous_elements
+            .iter()
+            .map(|e| (e.key(), e))
+            .collect();
+
+        let mut diff = TopologyDiff::default();
+        let mut keys = HashSet::new();
+        for element in self.elements() {
+            let key = element.key();
+            match previous_map.get(&key) {
+                None => diff.added.push(element),
+                Some(old) if **old != element => diff.changed.push(((*old).clone(), element)),
+                Some(_) => {}
+            }
+            keys.insert(key);
+        }
+
+        diff.removed = previous_elements
+            .iter()
+            .rev()
+            .filter(|e| !keys.contains(&e.key()))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+
+
+impl Element {
+    pub fn key(&self) -> ElementKey {
+        match self {
+            &Element::Exchange(ref option) => ElementKey::Exchange(option.name.clone()),
+            &Element::Queue(ref option) => ElementKey::Queue(option.name.clone()),
+            &Element::ExchangeBinding(ref option) => ElementKey::ExchangeBinding {
+                destination: option.destination.clone(),
+                source: option.source.clone(),
+                routing_key: option.routing_key.clone(),
+            },
+            &Element::QueueBinding(ref option) => ElementKey::QueueBinding {
+                queue: option.queue.clone(),
+                exchange: option.exchange.clone(),
+                routing_key: option.routing_key.clone(),
+            },
+        }
+    }
+
+
+    fn is_binding(&self) -> bool {
+        match self {
+            &Element::ExchangeBinding(_) |
+            &Element::QueueBinding(_) => true,
+            _ => false,
+        }
+    }
+
+
+    fn declare<In, Out>(self, local: LocalChannel<In, Out>) -> TopologyFuture<In, Out>
+    where
+        In: Income,
+        Out: Outgo,
+    {
+        match self {
+            Element::Exchange(option) => local.declare_exchange_with_option(option),
+            Element::Queue(option) => {
+                Box::new(local.declare_queue_with_option(option).map(|(_, local)| local))
+            }
+            Element::ExchangeBinding(option) => local.bind_exchange_with_option(option),
+            Element::QueueBinding(option) => local.bind_queue_with_option(option),
+        }
+    }
+
+
+    fn remove<In, Out>(
+        self,
+        local: LocalChannel<In, Out>,
+        removal: Removal,
+    ) -> TopologyFuture<In, Out>
+    where
+        In: Income,
+        Out: Outgo,
+    {
+        match self {
+            Element::Exchange(option) => {
+                local.delete_exchange(option.name, removal == Removal::IfUnused, false)
+            }
+            Element::Queue(option) => {
+                let option = delete_queue_option(option.name, removal);
+                Box::new(local.delete_queue_with_option(option).map(|(_, local)| local))
+            }
+            Element::ExchangeBinding(option) => local.unbind_exchange_with_option(option),
+            Element::QueueBinding(option) => {
+                let option = UnbindQueueOption {
+                    queue: option.queue,
+                    exchange: option.exchange,
+                    routing_key: option.routing_key,
+                    arguments: option.arguments,
+                };
+                local.unbind_queue_with_option(option)
+            }
+        }
+    }
+}
+
+
+impl fmt::Display for ElementKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ElementKey::Exchange(ref name) => write!(f, "exchange {:?}", name),
+            &ElementKey::Queue(ref name) => write!(f, "queue {:?}", name),
+            &ElementKey::ExchangeBinding {
+                ref destination,
+                ref source,
+                ref routing_key,
+            } => {
+                write!(
+                    f,
+                    "binding from exchange {:?} to exchange {:?} with {:?}",
+                    source,
+                    destination,
+                    routing_key
+                )
+            }
+            &ElementKey::QueueBinding {
+                ref queue,
+                ref exchange,
+                ref routing_key,
+            } => {
+                write!(
+                    f,
+                    "binding from exchange {:?} to queue {:?} with {:?}",
+                    exchange,
+                    queue,
+                    routing_key
+                )
+            }
+        }
+    }
+}
+
+
+
+impl TopologyDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+
+    /// Remove `removed` elements and then declare `added` elements.
+    /// Changed bindings are unbound and bound again. Changed exchanges and queues are left
+    /// as they are because server refuses to redeclare them with different options; delete
+    /// them explicitly if you really want to replace them.
+    ///
+    /// Removed exchanges and queues are deleted only if they are unused (`Removal::IfUnused`).
+    /// Use `apply_with_removal` with `Removal::Force` to delete them anyway.
+    pub fn apply<In, Out>(self, local: LocalChannel<In, Out>) -> TopologyFuture<In, Out>
+    where
+        In: Income,
+        Out: Outgo,
+    {
+        self.apply_with_removal(local, Removal::IfUnused)
+    }
+
+
+    /// Same with `apply` but removed exchanges and queues are deleted as `removal` says.
+    pub fn apply_with_removal<In, Out>(
+        self,
+        local: LocalChannel<In, Out>,
+        removal: Removal,
+    ) -> TopologyFuture<In, Out>
+    where
+        In: Income,
+        Out: Outgo,
+    {
+        run(local, self.steps(), removal)
+    }
+
+
+    fn steps(self) -> Vec<Step> {
+        let mut unbinds = Vec::new();
+        let mut rebinds = Vec::new();
+        for (old, new) in self.changed {
+            match old.is_binding() {
+                true => {
+                    unbinds.push(Step::Remove(old));
+                    rebinds.push(Step::Declare(new));
+                }
+                false => warn!("Changed {} is not applied", old.key()),
+            }
+        }
+
+        unbinds
+            .into_iter()
+            .chain(self.removed.into_iter().map(Step::Remove))
+            .chain(self.added.into_iter().map(Step::Declare))
+            .chain(rebinds)
+            .collect()
+    }
+}
+
+
+
+#[derive(Clone, PartialEq, Debug)]
+enum Step {
+    Declare(Element),
+    Remove(Element),
+}
+
+
+fn run<In, Out>(
+    local: LocalChannel<In, Out>,
+    steps: Vec<Step>,
+    removal: Removal,
+) -> TopologyFuture<In, Out>
+where
+    In: Income,
+    Out: Outgo,
+{
+    let fut = stream::iter_ok::<_, Rc<Error>>(steps).fold(local, move |local, step| {
+        let (key, fut) = match step {
+            Step::Declare(element) => (element.key(), element.declare(local)),
+            Step::Remove(element) => (element.key(), element.remove(local, removal)),
+        };
+        fut.map_err(move |e| topology_error(e, key))
+    });
+    Box::new(fut)
+}
+
+
+fn delete_queue_option(queue: String, removal: Removal) -> DeleteQueueOption {
+    let if_unused = removal == Removal::IfUnused;
+    DeleteQueueOption {
+        queue: queue,
+        if_unused: if_unused,
+        if_empty: if_unused,
+        is_no_wait: false,
+    }
+}
+
+
+fn topology_error(e: Rc<Error>, element: ElementKey) -> Rc<Error> {
+    let err = TopologyError {
+        element: element,
+        closed_by_server: find::<ClosedByServer>(&e).cloned(),
+        cause: e.to_string(),
+    };
+    Rc::new(Error::from(err))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amqpr_codec::args::FieldArgument;
+    use unsync::{ExchangeType, Arguments};
+
+    fn exchange(name: &str) -> DeclareExchangeOption {
+        DeclareExchangeOption {
+            name: name.into(),
+            typ: ExchangeType::Topic,
+            is_passive: false,
+            is_durable: true,
+            is_auto_delete: false,
+            is_internal: false,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        }
+    }
+
+
+    fn queue(name: &str) -> DeclareQueueOption {
+        DeclareQueueOption {
+            name: name.into(),
+            is_passive: false,
+            is_durable: true,
+            is_exclusive: false,
+            is_auto_delete: false,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        }
+    }
+
+
+    fn queue_binding(queue: &str, exchange: &str, routing_key: &str) -> BindQueueOption {
+        BindQueueOption {
+            queue: queue.into(),
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        }
+    }
+
+
+    fn exchange_binding(destination: &str, source: &str) -> BindExchangeOption {
+        BindExchangeOption {
+            destination: destination.into(),
+            source: source.into(),
+            routing_key: "#".into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        }
+    }
+
+
+    #[test]
+    fn elements_in_dependency_order() {
+        let topology = Topology::new()
+            .bind_queue(queue_binding("logs", "app", "log.#"))
+            .bind_exchange(exchange_binding("app", "upstream"))
+            .declare_queue(queue("logs"))
+            .declare_exchange(exchange("upstream"))
+            .declare_exchange(exchange("app"));
+
+        let keys: Vec<_> = topology.elements().iter().map(Element::key).collect();
+        assert_eq!(
+            keys,
+            vec![
+                ElementKey::Exchange("upstream".into()),
+                ElementKey::Exchange("app".into()),
+                ElementKey::Queue("logs".into()),
+                Element::ExchangeBinding(exchange_binding("app", "upstream")).key(),
+                Element::QueueBinding(queue_binding("logs", "app", "log.#")).key(),
+            ]
+        );
+    }
+
+
+    #[test]
+    fn diff_added_removed_and_changed() {
+        let previous = Topology::new()
+            .declare_exchange(exchange("app"))
+            .declare_queue(queue("old"))
+            .declare_queue(queue("logs"));
+        let mut durable_logs = queue("logs");
+        durable_logs.is_durable = false;
+        let topology = Topology::new()
+            .declare_exchange(exchange("app"))
+            .declare_queue(durable_logs.clone())
+            .declare_queue(queue("new"));
+
+        let diff = topology.diff(&previous);
+        assert_eq!(diff.added, vec![Element::Queue(queue("new"))]);
+        assert_eq!(diff.removed, vec![Element::Queue(queue("old"))]);
+        assert_eq!(
+            diff.changed,
+            vec![(Element::Queue(queue("logs")), Element::Queue(durable_logs))]
+        );
+        assert!(topology.diff(&topology).is_empty());
+    }
+
+
+    #[test]
+    fn remove_in_reverse_dependency_order() {
+        let previous = Topology::new()
+            .declare_exchange(exchange("app"))
+            .declare_queue(queue("logs"))
+            .bind_queue(queue_binding("logs", "app", "log.#"));
+
+        let diff = Topology::new().diff(&previous);
+        assert!(diff.added.is_empty());
+        assert_eq!(
+            diff.removed,
+            vec![
+                Element::QueueBinding(queue_binding("logs", "app", "log.#")),
+                Element::Queue(queue("logs")),
+                Element::Exchange(exchange("app")),
+            ]
+        );
+    }
+
+
+    #[test]
+    fn rebind_changed_binding_after_other_steps() {
+        let old_binding = queue_binding("logs", "app", "log.#");
+        let mut new_binding = old_binding.clone();
+        let all = FieldArgument::LongString("all".into());
+        new_binding.arguments = Arguments::new().insert("x-match", all);
+        let mut new_queue = queue("logs");
+        new_queue.is_durable = false;
+
+        let previous = Topology::new()
+            .declare_exchange(exchange("app"))
+            .declare_queue(queue("logs"))
+            .declare_queue(queue("old"))
+            .bind_queue(old_binding.clone());
+        let topology = Topology::new()
+            .declare_exchange(exchange("app"))
+            .declare_queue(new_queue)
+            .declare_queue(queue("new"))
+            .bind_queue(new_binding.clone());
+
+        // Changed queue is left as it is.
+        assert_eq!(
+            topology.diff(&previous).steps(),
+            vec![
+                Step::Remove(Element::QueueBinding(old_binding)),
+                Step::Remove(Element::Queue(queue("old"))),
+                Step::Declare(Element::Queue(queue("new"))),
+                Step::Declare(Element::QueueBinding(new_binding)),
+            ]
+        );
+    }
+
+
+    #[test]
+    fn remove_only_unused_queue_by_default() {
+        let option = delete_queue_option("logs".into(), Removal::IfUnused);
+        assert_eq!(option.queue, "logs");
+        assert!(option.if_unused);
+        assert!(option.if_empty);
+
+        let option = delete_queue_option("logs".into(), Removal::Force);
+        assert!(!option.if_unused);
+        assert!(!option.if_empty);
+    }
+}
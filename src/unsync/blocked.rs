@@ -0,0 +1,26 @@
+//! `Connection.Blocked` and `Connection.Unblocked` sent by server (RabbitMQ extension).
+//! Server blocks publishing connections when it hits a memory or disk alarm.
+
+use amqpr_codec::Frame;
+
+
+/// Notification of blocked state of a connection.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BlockedEvent {
+    /// Server stops reading from this connection. `reason` is given by server.
+    Blocked { reason: String },
+
+    Unblocked,
+}
+
+
+/// Returns an event if `frame` is `Connection.Blocked` or `Connection.Unblocked`.
+/// Those frames are consumed by the reader of the connection.
+pub(crate) fn blocked_event(frame: &Frame) -> Option<BlockedEvent> {
+    let m = frame.method().and_then(|c| c.connection())?;
+    match (m.blocked(), m.unblocked()) {
+        (Some(blocked), _) => Some(BlockedEvent::Blocked { reason: blocked.reason.clone() }),
+        (None, Some(_)) => Some(BlockedEvent::Unblocked),
+        (None, None) => None,
+    }
+}
@@ -0,0 +1,72 @@
+//! Stream which detects `Channel.Close` sent by server and fails with `ClosedByServer` error.
+//! Replying `Close-Ok` and marking the channel closed are done by the reader of the connection,
+//! so that they happen even if nobody polls this stream.
+
+use futures::{Stream, Poll, Async};
+
+use amqpr_codec::Frame;
+
+use std::rc::Rc;
+
+use super::Income;
+use super::local_channel::ChannelHandle;
+use errors::*;
+
+
+/// Watch `Channel.Close` on inbound frames of a local channel.
+pub(crate) struct ChannelCloseWatcher<In: Income> {
+    income: In,
+    channel: Rc<ChannelHandle>,
+}
+
+
+impl<In: Income> ChannelCloseWatcher<In> {
+    pub(crate) fn new(income: In, channel: Rc<ChannelHandle>) -> ChannelCloseWatcher<In> {
+        ChannelCloseWatcher {
+            income: income,
+            channel: channel,
+        }
+    }
+}
+
+
+impl<In: Income> Stream for ChannelCloseWatcher<In> {
+    type Item = Frame;
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, Rc<Error>> {
+        let frame = match try_ready!(self.income.poll()) {
+            Some(frame) => frame,
+            None => return Ok(Async::Ready(None)),
+        };
+
+        let id = self.channel.id;
+        let closed = frame
+            .method()
+            .and_then(|c| c.channel())
+            .and_then(|m| m.close())
+            .map(|m| closed_by_server(id, m.reply_code, &m.reply_text, m.class_id, m.method_id));
+
+        match closed {
+            Some(e) => Err(self.channel.channel_error().unwrap_or_else(|| Rc::new(Error::from(e)))),
+            None => Ok(Async::Ready(Some(frame))),
+        }
+    }
+}
+
+
+pub(crate) fn closed_by_server(
+    channel_id: u16,
+    reply_code: u16,
+    reply_text: &str,
+    class_id: u16,
+    method_id: u16,
+) -> ClosedByServer {
+    ClosedByServer {
+        channel_id: channel_id,
+        reply_code: reply_code,
+        reply_text: reply_text.into(),
+        class_id: class_id,
+        method_id: method_id,
+    }
+}
@@ -0,0 +1,423 @@
+use tokio_core::reactor::Handle;
+use futures::{Future, Sink, Poll, Async};
+use futures::task::{self, Task};
+use futures::unsync::mpsc::{self, UnboundedSender, UnboundedReceiver};
+
+use ex_futures::sink::UnsyncCloneable;
+
+use amqpr_codec::Frame;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::mem;
+use std::time::Duration;
+use std::rc::{Rc, Weak};
+use std::u16;
+
+use super::BoxedOutgo;
+use super::handshake::Tuning;
+use super::blocked::BlockedEvent;
+use super::local_channel::ChannelHandle;
+use super::reader::HeartbeatNotify;
+use errors::*;
+
+
+/// State of an AMQP connection shared by `GlobalChannel` and every `LocalChannel`,
+/// `PublishSink` and `SubscribeStream` derived from it.
+pub(crate) struct Connection {
+    pub(crate) handle: Handle,
+
+    // `None` if handshake is done by `amqpr_api::handshake::Handshaker`.
+    pub(crate) tuning: Option<Tuning>,
+
+    // Used to send a frame from `Drop` implementations.
+    outgo: UnsyncCloneable<BoxedOutgo>,
+
+    status: Cell<Status>,
+
+    // Set if connection is closed by server or the reader fails.
+    error: RefCell<Option<Rc<Error>>>,
+
+    // Ids of channels which are opened (or being opened) and not released yet.
+    channels: RefCell<BTreeSet<u16>>,
+
+    // Ids of dropped channels which are released when `Channel.Close-Ok` arrives.
+    closing_channels: RefCell<BTreeSet<u16>>,
+
+    // Handles of living channels, so that the reader can mark one closed by server.
+    handles: RefCell<HashMap<u16, Weak<ChannelHandle>>>,
+
+    // Allocation of a free channel id starts from this.
+    next_channel_id: Cell<u16>,
+
+    // Number of publishes which have started but have not finished writing frames yet.
+    publishing: Cell<usize>,
+
+    // Tasks waiting for `publishing` to become 0.
+    drain_waiters: RefCell<Vec<Task>>,
+
+    // Tasks waiting for connection to be closed.
+    close_waiters: RefCell<Vec<Task>>,
+
+    // True while server blocks this connection by `Connection.Blocked`.
+    blocked: Cell<bool>,
+
+    blocked_listeners: RefCell<Vec<UnboundedSender<BlockedEvent>>>,
+
+    // Tasks waiting for connection to be unblocked.
+    unblock_waiters: RefCell<Vec<Task>>,
+
+    // Task of the reader of inbound frames.
+    reader: RefCell<Option<Task>>,
+
+    // Heartbeat watching requested by `GlobalChannel::start_heartbeat`, taken by the reader.
+    heartbeat_watch: RefCell<Option<(Duration, HeartbeatNotify)>>,
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Status {
+    Open,
+    Closing,
+    Closed,
+}
+
+
+impl Connection {
+    pub(crate) fn new(
+        handle: Handle,
+        tuning: Option<Tuning>,
+        outgo: UnsyncCloneable<BoxedOutgo>,
+    ) -> Rc<Connection> {
+        Rc::new(Connection {
+            handle: handle,
+            tuning: tuning,
+            outgo: outgo,
+            status: Cell::new(Status::Open),
+            error: RefCell::new(None),
+            channels: RefCell::new(BTreeSet::new()),
+            closing_channels: RefCell::new(BTreeSet::new()),
+            handles: RefCell::new(HashMap::new()),
+            next_channel_id: Cell::new(1),
+            publishing: Cell::new(0),
+            drain_waiters: RefCell::new(Vec::new()),
+            close_waiters: RefCell::new(Vec::new()),
+            blocked: Cell::new(false),
+            blocked_listeners: RefCell::new(Vec::new()),
+            unblock_waiters: RefCell::new(Vec::new()),
+            reader: RefCell::new(None),
+            heartbeat_watch: RefCell::new(None),
+        })
+    }
+
+
+    /// Returns true if `Connection.Close` is started. No more publish is accepted.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.status.get() != Status::Open
+    }
+
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.status.get() == Status::Closed
+    }
+
+
+    pub(crate) fn start_closing(&self) {
+        if self.status.get() == Status::Open {
+            self.status.set(Status::Closing);
+        }
+    }
+
+
+    pub(crate) fn set_closed(&self) {
+        self.status.set(Status::Closed);
+        let closing = mem::replace(&mut *self.closing_channels.borrow_mut(), BTreeSet::new());
+        for channel_id in closing {
+            self.release_channel(channel_id);
+        }
+        for task in self.close_waiters.borrow_mut().drain(..) {
+            task.notify();
+        }
+        // Blocked publishers should notice that connection is closed.
+        for task in self.unblock_waiters.borrow_mut().drain(..) {
+            task.notify();
+        }
+    }
+
+
+    /// Mark connection as closed by server's `Connection.Close`, heartbeat timeout or an I/O
+    /// error.
+    pub(crate) fn set_failed(&self, e: Rc<Error>) {
+        *self.error.borrow_mut() = Some(e);
+        self.set_closed();
+    }
+
+
+    /// Returns an error if connection is closed by server or fails.
+    pub(crate) fn error(&self) -> Option<Rc<Error>> {
+        self.error.borrow().clone()
+    }
+
+
+    /// Notify current task when connection is closed.
+    pub(crate) fn notify_on_close(&self) {
+        let mut waiters = self.close_waiters.borrow_mut();
+        if !waiters.iter().any(|t| t.will_notify_current()) {
+            waiters.push(task::current());
+        }
+    }
+
+
+    pub(crate) fn is_blocked(&self) -> bool {
+        self.blocked.get()
+    }
+
+
+    pub(crate) fn set_blocked(&self, event: BlockedEvent) {
+        match event {
+            BlockedEvent::Blocked { ref reason } => {
+                warn!("Connection is blocked by server : {}", reason);
+                self.blocked.set(true);
+            }
+            BlockedEvent::Unblocked => {
+                info!("Connection is unblocked by server");
+                self.blocked.set(false);
+                for task in self.unblock_waiters.borrow_mut().drain(..) {
+                    task.notify();
+                }
+            }
+        }
+        self.blocked_listeners.borrow_mut().retain(|tx| {
+            tx.unbounded_send(event.clone()).is_ok()
+        });
+    }
+
+
+    /// Notify current task when connection is unblocked or closed.
+    pub(crate) fn notify_on_unblocked(&self) {
+        let mut waiters = self.unblock_waiters.borrow_mut();
+        if !waiters.iter().any(|t| t.will_notify_current()) {
+            waiters.push(task::current());
+        }
+    }
+
+
+    pub(crate) fn blocked_events(&self) -> UnboundedReceiver<BlockedEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.blocked_listeners.borrow_mut().push(tx);
+        rx
+    }
+
+
+    /// Ask the reader to watch heartbeat at `interval`.
+    pub(crate) fn watch_heartbeat(&self, interval: Duration, notify: HeartbeatNotify) {
+        *self.heartbeat_watch.borrow_mut() = Some((interval, notify));
+        if let Some(task) = self.reader.borrow_mut().take() {
+            task.notify();
+        }
+    }
+
+
+    /// Called by the reader so that it is notified by `watch_heartbeat`.
+    pub(crate) fn register_reader(&self) {
+        *self.reader.borrow_mut() = Some(task::current());
+    }
+
+
+    pub(crate) fn take_heartbeat_watch(&self) -> Option<(Duration, HeartbeatNotify)> {
+        self.heartbeat_watch.borrow_mut().take()
+    }
+
+
+    /// Returns an error if connection is closing.
+    pub(crate) fn check_open(&self) -> Result<(), Rc<Error>> {
+        if let Some(e) = self.error() {
+            return Err(e);
+        }
+        match self.is_closing() {
+            true => Err(Rc::new(Error::from(ConnectionClosed))),
+            false => Ok(()),
+        }
+    }
+
+
+    /// Maximum channel id negotiated by `Connection.Tune`.
+    pub(crate) fn channel_max(&self) -> u16 {
+        match self.tuning.map(|t| t.channel_max) {
+            Some(0) | None => u16::MAX,
+            Some(max) => max,
+        }
+    }
+
+
+    /// Maximum payload size of a content body frame negotiated by `Connection.Tune`.
+    /// `None` if there is no limit or tuning is unknown.
+    pub(crate) fn max_body_size(&self) -> Option<usize> {
+        match self.tuning.map(|t| t.frame_max) {
+            Some(0) | None => None,
+            // Frame header (7 bytes) and frame end (1 byte).
+            // Handshake rejects `frame_max` below `FRAME_MIN_SIZE` so this never saturates.
+            Some(max) => Some((max as usize).saturating_sub(8)),
+        }
+    }
+
+
+    /// Reserve `channel_id` for a new channel.
+    pub(crate) fn reserve_channel(&self, channel_id: u16) -> Result<(), Rc<Error>> {
+        let channel_max = self.channel_max();
+        if channel_id == 0 || channel_id > channel_max {
+            let e = ChannelIdError::OutOfRange {
+                channel_id: channel_id,
+                channel_max: channel_max,
+            };
+            return Err(Rc::new(Error::from(e)));
+        }
+        match self.channels.borrow_mut().insert(channel_id) {
+            true => Ok(()),
+            false => Err(Rc::new(Error::from(ChannelIdError::InUse(channel_id)))),
+        }
+    }
+
+
+    /// Reserve a free channel id for a new channel.
+    /// Ids are allocated in round robin so that an id which is just released is not reused
+    /// immediately.
+    pub(crate) fn allocate_channel(&self) -> Result<u16, Rc<Error>> {
+        let channel_max = self.channel_max();
+        let start = match self.next_channel_id.get() {
+            id if id == 0 || id > channel_max => 1,
+            id => id,
+        };
+
+        let mut channels = self.channels.borrow_mut();
+        let found = (start..=channel_max).chain(1..start).find(
+            |id| !channels.contains(id),
+        );
+        match found {
+            Some(id) => {
+                channels.insert(id);
+                self.next_channel_id.set(id.wrapping_add(1));
+                Ok(id)
+            }
+            None => Err(Rc::new(
+                Error::from(ChannelIdError::Exhausted { channel_max: channel_max }),
+            )),
+        }
+    }
+
+
+    pub(crate) fn release_channel(&self, channel_id: u16) {
+        self.channels.borrow_mut().remove(&channel_id);
+    }
+
+
+    /// Release `channel_id` when `Channel.Close-Ok` arrives or connection is closed.
+    pub(crate) fn release_channel_on_close_ok(&self, channel_id: u16) {
+        match self.is_closed() {
+            true => self.release_channel(channel_id),
+            false => {
+                self.closing_channels.borrow_mut().insert(channel_id);
+            }
+        }
+    }
+
+
+    pub(crate) fn register_handle(&self, handle: &Rc<ChannelHandle>) {
+        self.handles.borrow_mut().insert(handle.id, Rc::downgrade(handle));
+    }
+
+
+    pub(crate) fn unregister_handle(&self, channel_id: u16) {
+        self.handles.borrow_mut().remove(&channel_id);
+    }
+
+
+    /// Called by the reader when server closes a channel by `Channel.Close`.
+    /// `Close-Ok` is already sent.
+    pub(crate) fn on_channel_closed_by_server(&self, channel_id: u16, e: Rc<Error>) {
+        let handle = self.handles.borrow().get(&channel_id).and_then(|h| h.upgrade());
+        match handle {
+            Some(handle) => handle.set_closed_by_server(e),
+            // Every handle is dropped and `Channel.Close` sent in background crossed this.
+            None => self.on_channel_close_ok(channel_id),
+        }
+    }
+
+
+    /// Called by the reader when `Channel.Close-Ok` arrives.
+    pub(crate) fn on_channel_close_ok(&self, channel_id: u16) {
+        if self.closing_channels.borrow_mut().remove(&channel_id) {
+            debug!("Channel id {} is released", channel_id);
+            self.release_channel(channel_id);
+        }
+    }
+
+
+    /// Send a frame in background. Failure is just logged.
+    pub(crate) fn send_frame(&self, frame: Frame) {
+        let fut = self.outgo.clone().send(frame).then(|res| {
+            if let Err(e) = res {
+                warn!("Fail to send a frame in background : {:?}", e);
+            }
+            Ok(())
+        });
+        self.handle.spawn(fut);
+    }
+
+
+    /// Returns a future which completes when every on-going publish finishes.
+    pub(crate) fn drained(conn: &Rc<Connection>) -> Drained {
+        Drained { conn: conn.clone() }
+    }
+}
+
+
+
+/// Guard counting an on-going publish. Count is decreased when it is dropped.
+pub(crate) struct PublishGuard {
+    conn: Rc<Connection>,
+}
+
+
+impl PublishGuard {
+    pub(crate) fn new(conn: &Rc<Connection>) -> PublishGuard {
+        conn.publishing.set(conn.publishing.get() + 1);
+        PublishGuard { conn: conn.clone() }
+    }
+}
+
+
+impl Drop for PublishGuard {
+    fn drop(&mut self) {
+        let publishing = self.conn.publishing.get() - 1;
+        self.conn.publishing.set(publishing);
+        if publishing == 0 {
+            for task in self.conn.drain_waiters.borrow_mut().drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
+
+
+
+pub(crate) struct Drained {
+    conn: Rc<Connection>,
+}
+
+
+impl Future for Drained {
+    type Item = ();
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<(), Rc<Error>> {
+        if self.conn.publishing.get() == 0 {
+            return Ok(Async::Ready(()));
+        }
+        let mut waiters = self.conn.drain_waiters.borrow_mut();
+        if !waiters.iter().any(|t| t.will_notify_current()) {
+            waiters.push(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
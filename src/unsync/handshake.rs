@@ -0,0 +1,372 @@
+//! Handshake of AMQP connection which exposes the result of `Connection.Tune` negotiation.
+
+use futures::{Future, Stream, Sink};
+use futures::future::{self, Loop};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::write_all;
+use tokio_io::codec::Framed;
+
+use amqpr_codec::{Frame, Codec};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::connection::{ClassMethod, StartOkMethod, SecureOkMethod, TuneMethod,
+                                      TuneOkMethod, OpenMethod};
+use amqpr_codec::args::FieldArgument;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::io;
+
+use super::method::method_frame;
+use super::sasl::{Sasl, SaslMechanism, into_long_string};
+use uri::{AmqpUri, AuthMechanism};
+use errors::*;
+
+
+const PROTOCOL_HEADER: &'static [u8] = b"AMQP\x00\x00\x09\x01";
+
+
+/// The smallest `frame_max` which AMQP 0-9-1 allows peers to negotiate.
+pub const FRAME_MIN_SIZE: u32 = 4096;
+
+
+/// Option of handshake.
+/// `None` for tuning parameters means accepting the value proposed by server.
+/// `Some(0)` for `heartbeat` disables heartbeat regardless of server.
+#[derive(Clone, Debug)]
+pub struct HandshakeOption {
+    pub user: String,
+    pub pass: String,
+    pub virtual_host: String,
+    pub sasl: Sasl,
+    pub channel_max: Option<u16>,
+    pub frame_max: Option<u32>,
+    pub heartbeat: Option<u16>,
+}
+
+
+impl<'a> From<&'a AmqpUri> for HandshakeOption {
+    fn from(uri: &'a AmqpUri) -> HandshakeOption {
+        HandshakeOption {
+            user: uri.user.clone(),
+            pass: uri.pass.clone(),
+            virtual_host: uri.vhost.clone(),
+            sasl: match uri.auth_mechanism {
+                Some(AuthMechanism::AmqPlain) => Sasl::AmqPlain,
+                Some(AuthMechanism::External) => Sasl::External,
+                Some(AuthMechanism::Plain) | None => Sasl::Plain,
+            },
+            channel_max: uri.channel_max,
+            frame_max: uri.frame_max,
+            heartbeat: uri.heartbeat,
+        }
+    }
+}
+
+
+
+/// Result of `Connection.Tune` negotiation.
+/// 0 means "no limit" for `channel_max` and `frame_max`, and "disabled" for `heartbeat`.
+/// Non-zero `frame_max` is at least `FRAME_MIN_SIZE`; handshake fails otherwise.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tuning {
+    pub channel_max: u16,
+    pub frame_max: u32,
+
+    /// Heartbeat interval in seconds.
+    pub heartbeat: u16,
+}
+
+
+
+pub(crate) fn start_negotiation<S>(
+    socket: S,
+    option: HandshakeOption,
+) -> Box<Future<Item = (Framed<S, Codec>, Tuning), Error = Rc<Error>>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let mechanism = option.sasl.mechanism(&option.user, &option.pass);
+    let virtual_host = option.virtual_host.clone();
+
+    let fut = write_all(socket, PROTOCOL_HEADER)
+        .map_err(|e| Rc::new(Error::from(e)))
+        .and_then(|(socket, _)| {
+            recv(socket.framed(Codec), |method| {
+                method.start().map(|m| m.mechanisms.clone())
+            })
+        })
+        .and_then(move |(mechanisms, socket)| {
+            start_ok(socket, mechanism, &mechanisms)
+        })
+        .and_then(|(socket, mechanism)| secure(socket, mechanism))
+        .and_then(move |(tune, socket)| {
+            let tuning = Tuning {
+                channel_max: negotiate(option.channel_max, tune.channel_max),
+                frame_max: negotiate(option.frame_max, tune.frame_max),
+                heartbeat: negotiate_heartbeat(option.heartbeat, tune.heartbeat),
+            };
+            debug!("Negotiated tuning : {:?}", tuning);
+            check_frame_max(tuning.frame_max).map(|()| (socket, tuning))
+        })
+        .and_then(|(socket, tuning)| {
+            let tune_ok = TuneOkMethod {
+                channel_max: tuning.channel_max,
+                frame_max: tuning.frame_max,
+                heartbeat: tuning.heartbeat,
+            };
+            send(socket, ClassMethod::TuneOk(tune_ok)).map(move |socket| (socket, tuning))
+        })
+        .and_then(move |(socket, tuning)| {
+            let open = OpenMethod {
+                virtual_host: virtual_host,
+                reserved1: "".into(),
+                reserved2: false,
+            };
+            send(socket, ClassMethod::Open(open)).map(move |socket| (socket, tuning))
+        })
+        .and_then(|(socket, tuning)| {
+            recv(socket, |method| method.open_ok().map(|_| ())).map(move |((), socket)| {
+                (socket, tuning)
+            })
+        });
+
+    Box::new(fut)
+}
+
+
+/// Send `Connection.StartOk` with initial response of `mechanism`.
+fn start_ok<S>(
+    socket: Framed<S, Codec>,
+    mut mechanism: Box<SaslMechanism>,
+    offered: &str,
+) -> Box<Future<Item = (Framed<S, Codec>, Box<SaslMechanism>), Error = Rc<Error>>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let name = mechanism.name();
+    if !offered.split_whitespace().any(|m| m == name) {
+        let msg = format!(
+            "Server does not support {} mechanism (offered : {})",
+            name,
+            offered
+        );
+        return Box::new(future::err(Rc::new(Error::from(
+            io::Error::new(io::ErrorKind::Other, msg),
+        ))));
+    }
+
+    let response = match mechanism.initial_response().and_then(
+        |response| into_long_string(&name, response),
+    ) {
+        Ok(response) => response,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let start_ok = StartOkMethod {
+        client_properties: client_properties(),
+        mechanism: name,
+        response: response,
+        locale: "en_US".into(),
+    };
+    Box::new(send(socket, ClassMethod::StartOk(start_ok)).map(
+        move |socket| (socket, mechanism),
+    ))
+}
+
+
+enum AfterStartOk {
+    Secure(Vec<u8>),
+    Tune(TuneMethod),
+}
+
+
+/// Answer `Connection.Secure` challenges until `Connection.Tune` arrives.
+fn secure<S>(
+    socket: Framed<S, Codec>,
+    mechanism: Box<SaslMechanism>,
+) -> Box<Future<Item = (TuneMethod, Framed<S, Codec>), Error = Rc<Error>>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let fut = future::loop_fn((socket, mechanism), |(socket, mut mechanism)| {
+        let received = recv(socket, |method| {
+            method
+                .secure()
+                // `amqpr_codec` decodes challenge as UTF-8 string.
+                .map(|m| AfterStartOk::Secure(m.challenge.as_bytes().to_vec()))
+                .or_else(|| method.tune().cloned().map(AfterStartOk::Tune))
+        });
+        received.and_then(move |(next, socket)| match next {
+            AfterStartOk::Tune(tune) => {
+                Box::new(future::ok(Loop::Break((tune, socket)))) as
+                    Box<Future<Item = _, Error = Rc<Error>>>
+            }
+            AfterStartOk::Secure(challenge) => {
+                debug!("Receive Connection.Secure");
+                let name = mechanism.name();
+                let response = match mechanism.respond(&challenge).and_then(
+                    |response| into_long_string(&name, response),
+                ) {
+                    Ok(response) => response,
+                    Err(e) => return Box::new(future::err(e)) as Box<Future<Item = _, Error = _>>,
+                };
+                let secure_ok = SecureOkMethod { response: response };
+                let fut = send(socket, ClassMethod::SecureOk(secure_ok)).map(move |socket| {
+                    Loop::Continue((socket, mechanism))
+                });
+                Box::new(fut)
+            }
+        })
+    });
+    Box::new(fut)
+}
+
+
+/// Client wins if both values are non-zero, otherwise non-zero one wins.
+fn negotiate<T: Ord + Default + Copy>(client: Option<T>, server: T) -> T {
+    let zero = T::default();
+    match client {
+        None => server,
+        Some(client) if client == zero || server == zero => ::std::cmp::max(client, server),
+        Some(client) => ::std::cmp::min(client, server),
+    }
+}
+
+
+/// Same with `negotiate` except that client's 0 disables heartbeat even if server proposes one.
+fn negotiate_heartbeat(client: Option<u16>, server: u16) -> u16 {
+    match client {
+        Some(0) => 0,
+        client => negotiate(client, server),
+    }
+}
+
+
+/// A peer which can't take a frame of `FRAME_MIN_SIZE` violates the spec. Refuse it instead of
+/// splitting content into tiny (or empty) body frames.
+fn check_frame_max(frame_max: u32) -> Result<(), Rc<Error>> {
+    if frame_max != 0 && frame_max < FRAME_MIN_SIZE {
+        let msg = format!(
+            "Negotiated frame_max {} is less than {}, the minimum of AMQP 0-9-1",
+            frame_max,
+            FRAME_MIN_SIZE
+        );
+        return Err(Rc::new(Error::from(io::Error::new(io::ErrorKind::InvalidData, msg))));
+    }
+    Ok(())
+}
+
+
+fn client_properties() -> HashMap<String, FieldArgument> {
+    let mut capabilities = HashMap::new();
+    capabilities.insert("connection.blocked".into(), FieldArgument::Boolean(true));
+
+    let mut props = HashMap::new();
+    props.insert("product".into(), FieldArgument::LongString("amqpr".into()));
+    props.insert(
+        "version".into(),
+        FieldArgument::LongString(env!("CARGO_PKG_VERSION").into()),
+    );
+    props.insert("platform".into(), FieldArgument::LongString("Rust".into()));
+    props.insert("capabilities".into(), FieldArgument::FieldTable(capabilities));
+    props
+}
+
+
+fn send<S>(
+    socket: Framed<S, Codec>,
+    method: ClassMethod,
+) -> Box<Future<Item = Framed<S, Codec>, Error = Rc<Error>>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let frame = method_frame(0, MethodPayload::Connection(method));
+    Box::new(socket.send(frame).map_err(|e| Rc::new(Error::from(e))))
+}
+
+
+/// Receive a connection class method. Any other frame is an error while handshaking.
+/// `Connection.Close` (e.g. 403 ACCESS_REFUSED) becomes `ClosedByServer` error.
+fn recv<S, F, T>(
+    socket: Framed<S, Codec>,
+    extract: F,
+) -> Box<Future<Item = (T, Framed<S, Codec>), Error = Rc<Error>>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+    F: FnOnce(&ClassMethod) -> Option<T> + 'static,
+    T: 'static,
+{
+    let fut = socket
+        .into_future()
+        .map_err(|(e, _)| Rc::new(Error::from(e)))
+        .and_then(move |(frame, socket)| {
+            let res = frame.as_ref().and_then(Frame::method).and_then(
+                |c| c.connection(),
+            );
+            if let Some(close) = res.and_then(|m| m.close()) {
+                let e = ClosedByServer {
+                    channel_id: 0,
+                    reply_code: close.reply_code,
+                    reply_text: close.reply_text.clone(),
+                    class_id: close.class_id,
+                    method_id: close.method_id,
+                };
+                return Err(Rc::new(Error::from(e)));
+            }
+            match res.and_then(extract) {
+                Some(res) => Ok((res, socket)),
+                None => {
+                    let msg = format!("Unexpected frame while handshaking : {:?}", frame);
+                    Err(Rc::new(Error::from(io::Error::new(io::ErrorKind::InvalidData, msg))))
+                }
+            }
+        });
+    Box::new(fut)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_server_value_if_client_has_no_preference() {
+        assert_eq!(negotiate(None, 2047u16), 2047);
+        assert_eq!(negotiate(None, 0u32), 0);
+    }
+
+
+    #[test]
+    fn lower_limit_wins() {
+        assert_eq!(negotiate(Some(64u16), 2047), 64);
+        assert_eq!(negotiate(Some(4096u16), 2047), 2047);
+    }
+
+
+    #[test]
+    fn zero_limit_means_no_limit() {
+        assert_eq!(negotiate(Some(0u32), 131072), 131072);
+        assert_eq!(negotiate(Some(131072u32), 0), 131072);
+        assert_eq!(negotiate(Some(0u32), 0), 0);
+    }
+
+
+    #[test]
+    fn client_can_disable_heartbeat() {
+        assert_eq!(negotiate_heartbeat(Some(0), 60), 0);
+        assert_eq!(negotiate_heartbeat(Some(30), 60), 30);
+        assert_eq!(negotiate_heartbeat(Some(30), 0), 30);
+        assert_eq!(negotiate_heartbeat(None, 60), 60);
+        assert_eq!(negotiate_heartbeat(None, 0), 0);
+    }
+
+
+    #[test]
+    fn refuse_too_small_frame_max() {
+        assert!(check_frame_max(0).is_ok());
+        assert!(check_frame_max(FRAME_MIN_SIZE).is_ok());
+        assert!(check_frame_max(131072).is_ok());
+        assert!(check_frame_max(FRAME_MIN_SIZE - 1).is_err());
+        assert!(check_frame_max(7).is_err());
+    }
+}
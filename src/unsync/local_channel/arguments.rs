@@ -0,0 +1,342 @@
+use amqpr_codec::args::FieldArgument;
+
+use std::collections::HashMap;
+
+
+/// Builder of the arguments field table of `Queue.Declare`, `Exchange.Declare`, `Queue.Bind`,
+/// `Exchange.Bind` and `Basic.Consume`.
+///
+/// ```ignore
+/// let arguments = Arguments::new()
+///     .message_ttl(60_000)
+///     .dead_letter_exchange("dlx")
+///     .queue_type(QueueType::Quorum)
+///     .insert("x-custom", FieldArgument::Boolean(true));
+/// ```
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Arguments {
+    table: HashMap<String, FieldArgument>,
+}
+
+
+/// Value of `x-overflow` argument.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Overflow {
+    DropHead,
+    RejectPublish,
+    RejectPublishDlx,
+}
+
+
+/// Value of `x-match` argument of a binding to headers exchange.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeadersMatch {
+    /// Every header of the binding must match.
+    All,
+
+    /// At least one header of the binding must match.
+    Any,
+}
+
+
+/// Value of `x-queue-type` argument.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueueType {
+    Classic,
+    Quorum,
+    Stream,
+}
+
+
+impl Arguments {
+    pub fn new() -> Arguments {
+        Arguments { table: HashMap::new() }
+    }
+
+
+    /// Set arbitrary field table value.
+    pub fn insert<S: Into<String>>(mut self, key: S, value: FieldArgument) -> Arguments {
+        self.table.insert(key.into(), value);
+        self
+    }
+
+
+    pub fn get(&self, key: &str) -> Option<&FieldArgument> {
+        self.table.get(key)
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+
+    pub fn as_table(&self) -> &HashMap<String, FieldArgument> {
+        &self.table
+    }
+
+
+    pub fn into_table(self) -> HashMap<String, FieldArgument> {
+        self.table
+    }
+
+
+    /// Rough size of the encoded field table.
+    pub(crate) fn estimated_size(&self) -> usize {
+        table_size(&self.table)
+    }
+
+
+    // Queue arguments
+
+    /// `x-message-ttl` : How long a message can live in a queue in milliseconds.
+    pub fn message_ttl(self, millis: u32) -> Arguments {
+        self.insert("x-message-ttl", FieldArgument::LongLongInt(millis as i64))
+    }
+
+
+    /// `x-expires` : How long a queue can be unused before it is deleted in milliseconds.
+    pub fn expires(self, millis: u32) -> Arguments {
+        self.insert("x-expires", FieldArgument::LongLongInt(millis as i64))
+    }
+
+
+    /// `x-dead-letter-exchange` : Exchange to which rejected or expired messages are
+    /// republished.
+    pub fn dead_letter_exchange<S: Into<String>>(self, exchange: S) -> Arguments {
+        self.insert(
+            "x-dead-letter-exchange",
+            FieldArgument::LongString(exchange.into()),
+        )
+    }
+
+
+    /// `x-dead-letter-routing-key` : Routing key used when a message is dead-lettered.
+    pub fn dead_letter_routing_key<S: Into<String>>(self, routing_key: S) -> Arguments {
+        self.insert(
+            "x-dead-letter-routing-key",
+            FieldArgument::LongString(routing_key.into()),
+        )
+    }
+
+
+    /// `x-max-length` : Maximum number of ready messages in a queue.
+    pub fn max_length(self, max: u32) -> Arguments {
+        self.insert("x-max-length", FieldArgument::LongLongInt(max as i64))
+    }
+
+
+    /// `x-max-length-bytes` : Maximum total size of ready message bodies in a queue.
+    pub fn max_length_bytes(self, max: u64) -> Arguments {
+        self.insert("x-max-length-bytes", FieldArgument::LongLongInt(max as i64))
+    }
+
+
+    /// `x-overflow` : Behaviour when a queue reaches its maximum length.
+    pub fn overflow(self, overflow: Overflow) -> Arguments {
+        let value = match overflow {
+            Overflow::DropHead => "drop-head",
+            Overflow::RejectPublish => "reject-publish",
+            Overflow::RejectPublishDlx => "reject-publish-dlx",
+        };
+        self.insert("x-overflow", FieldArgument::LongString(value.into()))
+    }
+
+
+    /// `x-queue-type` : Type of a queue.
+    pub fn queue_type(self, typ: QueueType) -> Arguments {
+        let value = match typ {
+            QueueType::Classic => "classic",
+            QueueType::Quorum => "quorum",
+            QueueType::Stream => "stream",
+        };
+        self.insert("x-queue-type", FieldArgument::LongString(value.into()))
+    }
+
+
+    /// `x-max-priority` : Maximum priority which a queue supports.
+    pub fn max_priority(self, max: u8) -> Arguments {
+        self.insert("x-max-priority", FieldArgument::LongLongInt(max as i64))
+    }
+
+
+    /// `x-single-active-consumer` : Only one consumer receives messages at a time.
+    pub fn single_active_consumer(self, enabled: bool) -> Arguments {
+        self.insert(
+            "x-single-active-consumer",
+            FieldArgument::Boolean(enabled),
+        )
+    }
+
+
+    // Exchange arguments
+
+    /// `alternate-exchange` : Exchange to which unroutable messages are sent.
+    pub fn alternate_exchange<S: Into<String>>(self, exchange: S) -> Arguments {
+        self.insert(
+            "alternate-exchange",
+            FieldArgument::LongString(exchange.into()),
+        )
+    }
+
+
+    // Binding arguments
+
+    /// `x-match` : How headers of a message are matched against a binding to headers exchange.
+    /// Other arguments of the binding are treated as headers to match.
+    pub fn x_match(self, x_match: HeadersMatch) -> Arguments {
+        let value = match x_match {
+            HeadersMatch::All => "all",
+            HeadersMatch::Any => "any",
+        };
+        self.insert("x-match", FieldArgument::LongString(value.into()))
+    }
+
+
+    // Consume arguments
+
+    /// `x-priority` : Priority of a consumer.
+    pub fn consumer_priority(self, priority: i32) -> Arguments {
+        self.insert("x-priority", FieldArgument::LongLongInt(priority as i64))
+    }
+}
+
+
+fn table_size(table: &HashMap<String, FieldArgument>) -> usize {
+    // Size of the table (4 bytes), and length (1 byte) and name of each key.
+    4 + table.iter().map(|(key, value)| 1 + key.len() + value_size(value)).sum::<usize>()
+}
+
+
+fn value_size(value: &FieldArgument) -> usize {
+    // Type tag (1 byte) and length (4 bytes) or value (at most 8 bytes).
+    match *value {
+        FieldArgument::LongString(ref s) => 5 + s.len(),
+        FieldArgument::FieldArray(ref values) => 5 + values.iter().map(value_size).sum::<usize>(),
+        FieldArgument::FieldTable(ref table) => 1 + table_size(table),
+        _ => 9,
+    }
+}
+
+
+impl From<HashMap<String, FieldArgument>> for Arguments {
+    fn from(table: HashMap<String, FieldArgument>) -> Arguments {
+        Arguments { table: table }
+    }
+}
+
+
+impl From<Arguments> for HashMap<String, FieldArgument> {
+    fn from(arguments: Arguments) -> HashMap<String, FieldArgument> {
+        arguments.table
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long_string(s: &str) -> FieldArgument {
+        FieldArgument::LongString(s.into())
+    }
+
+
+    #[test]
+    fn build_queue_arguments() {
+        let arguments = Arguments::new()
+            .message_ttl(60_000)
+            .expires(1_800_000)
+            .dead_letter_exchange("dlx")
+            .dead_letter_routing_key("dead")
+            .max_length(1000)
+            .max_length_bytes(1 << 32)
+            .max_priority(10)
+            .single_active_consumer(true);
+
+        assert_eq!(arguments.as_table().len(), 8);
+        assert_eq!(arguments.get("x-message-ttl"), Some(&FieldArgument::LongLongInt(60_000)));
+        assert_eq!(arguments.get("x-expires"), Some(&FieldArgument::LongLongInt(1_800_000)));
+        assert_eq!(arguments.get("x-dead-letter-exchange"), Some(&long_string("dlx")));
+        assert_eq!(arguments.get("x-dead-letter-routing-key"), Some(&long_string("dead")));
+        assert_eq!(arguments.get("x-max-length"), Some(&FieldArgument::LongLongInt(1000)));
+        assert_eq!(
+            arguments.get("x-max-length-bytes"),
+            Some(&FieldArgument::LongLongInt(1 << 32))
+        );
+        assert_eq!(arguments.get("x-max-priority"), Some(&FieldArgument::LongLongInt(10)));
+        assert_eq!(
+            arguments.get("x-single-active-consumer"),
+            Some(&FieldArgument::Boolean(true))
+        );
+    }
+
+
+    #[test]
+    fn encode_enum_values() {
+        let overflow = |o| Arguments::new().overflow(o).into_table().remove("x-overflow");
+        assert_eq!(overflow(Overflow::DropHead), Some(long_string("drop-head")));
+        assert_eq!(overflow(Overflow::RejectPublish), Some(long_string("reject-publish")));
+        assert_eq!(
+            overflow(Overflow::RejectPublishDlx),
+            Some(long_string("reject-publish-dlx"))
+        );
+
+        let queue_type = |t| Arguments::new().queue_type(t).into_table().remove("x-queue-type");
+        assert_eq!(queue_type(QueueType::Classic), Some(long_string("classic")));
+        assert_eq!(queue_type(QueueType::Quorum), Some(long_string("quorum")));
+        assert_eq!(queue_type(QueueType::Stream), Some(long_string("stream")));
+
+        let x_match = |m| Arguments::new().x_match(m).into_table().remove("x-match");
+        assert_eq!(x_match(HeadersMatch::All), Some(long_string("all")));
+        assert_eq!(x_match(HeadersMatch::Any), Some(long_string("any")));
+    }
+
+
+    #[test]
+    fn build_exchange_and_consume_arguments() {
+        let arguments = Arguments::new().alternate_exchange("unrouted").consumer_priority(-5);
+        assert_eq!(arguments.get("alternate-exchange"), Some(&long_string("unrouted")));
+        assert_eq!(arguments.get("x-priority"), Some(&FieldArgument::LongLongInt(-5)));
+    }
+
+
+    #[test]
+    fn later_value_wins() {
+        let arguments = Arguments::new()
+            .message_ttl(1000)
+            .insert("x-message-ttl", FieldArgument::LongLongInt(2000));
+        assert_eq!(arguments.get("x-message-ttl"), Some(&FieldArgument::LongLongInt(2000)));
+        assert_eq!(arguments.as_table().len(), 1);
+    }
+
+
+    #[test]
+    fn convert_from_and_into_table() {
+        assert!(Arguments::new().is_empty());
+        assert_eq!(Arguments::default(), Arguments::new());
+
+        let mut table = HashMap::new();
+        table.insert("x-custom".to_string(), FieldArgument::Boolean(false));
+        let arguments = Arguments::from(table.clone());
+        assert!(!arguments.is_empty());
+        assert_eq!(HashMap::from(arguments), table);
+    }
+
+
+    #[test]
+    fn estimate_size_of_nested_values() {
+        assert_eq!(Arguments::new().estimated_size(), 4);
+
+        let arguments = Arguments::new().insert("x-a", long_string("abc"));
+        assert_eq!(arguments.estimated_size(), 4 + 4 + 8);
+
+        let mut table = HashMap::new();
+        table.insert("k".to_string(), FieldArgument::Boolean(true));
+        let arguments = Arguments::new()
+            .insert("t", FieldArgument::FieldTable(table))
+            .insert("a", FieldArgument::FieldArray(vec![long_string("xy")]));
+        assert_eq!(arguments.estimated_size(), 4 + (2 + 1 + 4 + 2 + 9) + (2 + 5 + 7));
+    }
+}
@@ -0,0 +1,461 @@
+use futures::{Future, Sink, Stream, Poll, StartSend, Async, AsyncSink};
+
+use amqpr_codec::Frame;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::confirm::{ClassMethod, SelectMethod};
+
+use bytes::Bytes;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::collections::Bound::{Included, Unbounded};
+use std::rc::Rc;
+
+use unsync::{Income, Outgo, BoxedIncome};
+use unsync::method::call_method;
+use super::{LocalChannel, LocalChannelFuture, ChannelHandle, PublishSink, PublishOption,
+            Message, PublishItem, ReturnedMessage};
+use super::publish::publish_sink;
+use super::returned::{ReturnChecker, ReturnAssembler};
+use errors::*;
+
+
+pub type ConfirmSinkFuture<Out, T = Bytes> = Box<
+    Future<Item = ConfirmPublishSink<Out, T>, Error = Rc<Error>>,
+>;
+
+
+/// Result of a published item reported by `Basic.Ack` or `Basic.Nack`.
+#[derive(Clone, Debug)]
+pub struct Confirmation {
+    /// Sequence number of the item on the channel, starting from 1.
+    pub delivery_tag: u64,
+
+    /// False if server rejected the item by `Basic.Nack`. You may want to publish it again.
+    pub is_ack: bool,
+
+    pub message: Message,
+
+    /// Set if server returned the item by `Basic.Return`. Server acks a returned item, so you
+    /// should check this as well as `is_ack` if you publish with `mandatory`.
+    pub returned: Option<ReturnedMessage>,
+}
+
+
+struct Pending {
+    message: Message,
+    exchange: String,
+    routing_key: String,
+    is_mandatory: bool,
+    returned: Option<ReturnedMessage>,
+}
+
+
+impl Pending {
+    /// Server sends `Basic.Return` before `Basic.Ack` of the same item, in the order of
+    /// publishing. So a returned message belongs to the earliest mandatory item to the same
+    /// exchange and routing key which is not returned yet.
+    fn awaits_return(&self, returned: &ReturnedMessage) -> bool {
+        self.is_mandatory && self.returned.is_none() && self.exchange == returned.exchange &&
+            self.routing_key == returned.routing_key
+    }
+}
+
+
+/// Items waiting for `Basic.Ack` or `Basic.Nack`, keyed by delivery tag.
+struct Unconfirmed<P> {
+    next_tag: u64,
+    items: BTreeMap<u64, P>,
+}
+
+
+impl<P> Unconfirmed<P> {
+    fn new() -> Unconfirmed<P> {
+        Unconfirmed {
+            next_tag: 1,
+            items: BTreeMap::new(),
+        }
+    }
+
+
+    /// Assign the next delivery tag to `item`.
+    fn push(&mut self, item: P) -> u64 {
+        let tag = self.next_tag;
+        self.items.insert(tag, item);
+        self.next_tag += 1;
+        tag
+    }
+
+
+    /// Remove items confirmed by `Basic.Ack` or `Basic.Nack` in the order of delivery tags.
+    /// Unknown tags are ignored.
+    fn confirm(&mut self, delivery_tag: u64, multiple: bool) -> Vec<(u64, P)> {
+        let tags: Vec<u64> = match (multiple, delivery_tag) {
+            // Delivery tag 0 with `multiple` means every unconfirmed item.
+            (true, 0) => self.items.keys().cloned().collect(),
+            (true, tag) => {
+                self.items
+                    .range((Unbounded, Included(tag)))
+                    .map(|(tag, _)| *tag)
+                    .collect()
+            }
+            (false, tag) => vec![tag],
+        };
+
+        let mut confirmed = Vec::with_capacity(tags.len());
+        for tag in tags {
+            match self.items.remove(&tag) {
+                Some(item) => confirmed.push((tag, item)),
+                None => warn!("Confirmation of unknown delivery tag {} is ignored", tag),
+            }
+        }
+        confirmed
+    }
+
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+
+    /// Delivery tag of the first item which satisfies `predicate`.
+    fn find<F: FnMut(&P) -> bool>(&self, mut predicate: F) -> Option<u64> {
+        self.items.iter().find(|&(_, item)| predicate(item)).map(|(tag, _)| *tag)
+    }
+
+
+    fn get_mut(&mut self, tag: u64) -> Option<&mut P> {
+        self.items.get_mut(&tag)
+    }
+}
+
+
+
+pub fn confirm_select<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Confirm(ClassMethod::Select(SelectMethod { no_wait: false }));
+
+    let selected = call_method(ch.income, ch.outgo, ch_id, method, |frame| {
+        frame.method().and_then(|c| c.confirm()).and_then(
+            |m| m.select_ok(),
+        ).map(|_| ())
+    });
+
+    let fut = selected.map(move |((), income, outgo)| {
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn confirm_publish_sink<In, Out, T>(
+    ch: LocalChannel<In, Out>,
+    option: PublishOption,
+) -> ConfirmSinkFuture<Out, T>
+where
+    In: Income,
+    Out: Outgo,
+    T: PublishItem + 'static,
+{
+    let fut = confirm_select(ch).map(move |ch| {
+        let mut checker = ReturnChecker::new();
+        let income = ch.income.filter(move |frame| {
+            let accepted = is_confirm(frame) || checker.check(frame);
+            if !accepted {
+                debug!("Frame on a confirm channel is ignored : {:?}", frame);
+            }
+            accepted
+        });
+
+        ConfirmPublishSink {
+            inner: publish_sink(ch.channel.clone(), option, ch.outgo),
+            income: Box::new(income),
+            channel: ch.channel,
+            unconfirmed: Unconfirmed::new(),
+            confirmed: VecDeque::new(),
+            assembler: ReturnAssembler::new(),
+        }
+    });
+
+    Box::new(fut)
+}
+
+
+fn is_confirm(frame: &Frame) -> bool {
+    frame
+        .method()
+        .and_then(|c| c.basic())
+        .map(|m| m.ack().is_some() || m.nack().is_some())
+        .unwrap_or(false)
+}
+
+
+
+/// A sink to publish items on a channel in confirm mode. It is also a stream of `Confirmation`
+/// in the order server confirms them. Use `split` to handle them separately.
+/// Items are `Bytes` by default. Like `PublishSink`, `Message` and `RoutedMessage` are also
+/// available.
+///
+/// Items are kept until they are confirmed, so you should keep polling the stream.
+/// Delivery tags are counted on the whole channel, so the sink takes the channel over and there
+/// is no way to publish anything else on it. Other frames on the channel (e.g. deliveries) are
+/// ignored; declare exchanges and queues before, and consume on another channel.
+///
+/// `Basic.Return` is also handled by this stream and is reported by `Confirmation::returned`.
+/// Since server returns an item before acking it, a returned message is correlated with the
+/// earliest pending `mandatory` item published to the same exchange and routing key.
+pub struct ConfirmPublishSink<Out: Outgo, T: PublishItem = Bytes> {
+    inner: PublishSink<Out, T>,
+    income: BoxedIncome,
+    channel: Rc<ChannelHandle>,
+    unconfirmed: Unconfirmed<Pending>,
+    confirmed: VecDeque<Confirmation>,
+    assembler: ReturnAssembler,
+}
+
+
+impl<Out: Outgo, T: PublishItem> ConfirmPublishSink<Out, T> {
+    /// Delivery tag which will be assigned to the next item.
+    pub fn next_delivery_tag(&self) -> u64 {
+        self.unconfirmed.next_tag
+    }
+
+
+    /// Number of items which are not confirmed yet.
+    pub fn pending_count(&self) -> usize {
+        self.unconfirmed.len()
+    }
+
+
+    /// Same with `PublishSink::set_high_water_mark`.
+    pub fn set_high_water_mark(&mut self, bytes: usize) {
+        self.inner.set_high_water_mark(bytes);
+    }
+
+
+    fn confirm(&mut self, delivery_tag: u64, multiple: bool, is_ack: bool) {
+        for (tag, pending) in self.unconfirmed.confirm(delivery_tag, multiple) {
+            self.confirmed.push_back(Confirmation {
+                delivery_tag: tag,
+                is_ack: is_ack,
+                message: pending.message,
+                returned: pending.returned,
+            });
+        }
+    }
+
+
+    fn on_returned(&mut self, returned: ReturnedMessage) {
+        let tag = self.unconfirmed.find(|pending| pending.awaits_return(&returned));
+        match tag.and_then(|tag| self.unconfirmed.get_mut(tag)) {
+            Some(pending) => pending.returned = Some(returned),
+            None => warn!("Returned message does not match any pending item : {:?}", returned),
+        }
+    }
+}
+
+
+impl<Out: Outgo, T: PublishItem> Sink for ConfirmPublishSink<Out, T> {
+    type SinkItem = T;
+    type SinkError = Rc<Error>;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, Self::SinkError> {
+        if let Async::NotReady = self.inner.poll_ready()? {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let (message, option) = item.into_publish(self.inner.option());
+        self.inner.push_message(message.clone(), &option)?;
+        self.unconfirmed.push(Pending {
+            message: message,
+            exchange: option.exchange,
+            routing_key: option.routing_key,
+            is_mandatory: option.is_mandatory,
+            returned: None,
+        });
+        Ok(AsyncSink::Ready)
+    }
+
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+}
+
+
+impl<Out: Outgo, T: PublishItem> Stream for ConfirmPublishSink<Out, T> {
+    type Item = Confirmation;
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<Option<Confirmation>, Rc<Error>> {
+        loop {
+            if let Some(confirmation) = self.confirmed.pop_front() {
+                return Ok(Async::Ready(Some(confirmation)));
+            }
+            if let Some(e) = self.channel.error() {
+                return Err(e);
+            }
+            if self.channel.conn.is_closed() || self.channel.is_closed() {
+                return Ok(Async::Ready(None));
+            }
+
+            let frame = match self.income.poll()? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => {
+                    self.channel.conn.notify_on_close();
+                    self.channel.notify_on_close();
+                    return Ok(Async::NotReady);
+                }
+            };
+
+            let basic = frame.method().and_then(|c| c.basic());
+            if let Some(ack) = basic.and_then(|m| m.ack()) {
+                self.confirm(ack.delivery_tag, ack.multiple, true);
+            } else if let Some(nack) = basic.and_then(|m| m.nack()) {
+                self.confirm(nack.delivery_tag, nack.multiple, false);
+            } else if let Some(returned) = self.assembler.push(&frame) {
+                self.on_returned(returned);
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unconfirmed(count: usize) -> Unconfirmed<&'static str> {
+        let mut unconfirmed = Unconfirmed::new();
+        for item in ["a", "b", "c", "d"].iter().take(count) {
+            unconfirmed.push(*item);
+        }
+        unconfirmed
+    }
+
+
+    #[test]
+    fn assign_tags_from_one() {
+        let mut unconfirmed = Unconfirmed::new();
+        assert_eq!(unconfirmed.push("a"), 1);
+        assert_eq!(unconfirmed.push("b"), 2);
+        assert_eq!(unconfirmed.next_tag, 3);
+        assert_eq!(unconfirmed.len(), 2);
+    }
+
+
+    #[test]
+    fn confirm_single() {
+        let mut unconfirmed = unconfirmed(3);
+        assert_eq!(unconfirmed.confirm(2, false), vec![(2, "b")]);
+        assert_eq!(unconfirmed.confirm(1, false), vec![(1, "a")]);
+        assert_eq!(unconfirmed.len(), 1);
+    }
+
+
+    #[test]
+    fn confirm_multiple_up_to_tag() {
+        let mut unconfirmed = unconfirmed(4);
+        unconfirmed.confirm(2, false);
+        assert_eq!(unconfirmed.confirm(3, true), vec![(1, "a"), (3, "c")]);
+        assert_eq!(unconfirmed.confirm(3, true), vec![]);
+        assert_eq!(unconfirmed.len(), 1);
+    }
+
+
+    #[test]
+    fn confirm_multiple_with_tag_zero_means_all() {
+        let mut unconfirmed = unconfirmed(3);
+        assert_eq!(unconfirmed.confirm(0, true), vec![(1, "a"), (2, "b"), (3, "c")]);
+        assert_eq!(unconfirmed.len(), 0);
+        assert_eq!(unconfirmed.push("d"), 4);
+    }
+
+
+    #[test]
+    fn confirm_multiple_with_max_tag() {
+        let mut unconfirmed = unconfirmed(2);
+        assert_eq!(unconfirmed.confirm(u64::max_value(), true), vec![(1, "a"), (2, "b")]);
+    }
+
+
+    #[test]
+    fn find_first_tag() {
+        let mut unconfirmed = unconfirmed(4);
+        unconfirmed.confirm(1, false);
+        assert_eq!(unconfirmed.find(|item| *item != "a"), Some(2));
+        assert_eq!(unconfirmed.find(|item| *item == "a"), None);
+    }
+
+
+    fn pending(routing_key: &str, is_mandatory: bool) -> Pending {
+        Pending {
+            message: Message::new("body"),
+            exchange: "logs".into(),
+            routing_key: routing_key.into(),
+            is_mandatory: is_mandatory,
+            returned: None,
+        }
+    }
+
+
+    fn returned(routing_key: &str) -> ReturnedMessage {
+        ReturnedMessage {
+            reply_code: 312,
+            reply_text: "NO_ROUTE".into(),
+            exchange: "logs".into(),
+            routing_key: routing_key.into(),
+            message: Message::new("body"),
+        }
+    }
+
+
+    #[test]
+    fn match_return_with_earliest_mandatory_item() {
+        let mut unconfirmed = Unconfirmed::new();
+        unconfirmed.push(pending("a", false));
+        unconfirmed.push(pending("b", true));
+        unconfirmed.push(pending("a", true));
+        unconfirmed.push(pending("a", true));
+
+        let returned = returned("a");
+        let tag = unconfirmed.find(|pending| pending.awaits_return(&returned));
+        assert_eq!(tag, Some(3));
+
+        // The next return of the same routing key belongs to the next item.
+        unconfirmed.get_mut(3).unwrap().returned = Some(returned.clone());
+        let tag = unconfirmed.find(|pending| pending.awaits_return(&returned));
+        assert_eq!(tag, Some(4));
+    }
+
+
+    #[test]
+    fn ignore_return_without_mandatory_item() {
+        let mut unconfirmed = Unconfirmed::new();
+        unconfirmed.push(pending("a", false));
+        unconfirmed.push(pending("b", true));
+        let returned = returned("a");
+        assert_eq!(unconfirmed.find(|pending| pending.awaits_return(&returned)), None);
+    }
+
+
+    #[test]
+    fn ignore_unknown_tag() {
+        let mut unconfirmed = unconfirmed(2);
+        assert_eq!(unconfirmed.confirm(0, false), vec![]);
+        assert_eq!(unconfirmed.confirm(5, false), vec![]);
+        assert_eq!(unconfirmed.confirm(1, false), vec![(1, "a")]);
+        assert_eq!(unconfirmed.confirm(1, false), vec![]);
+        assert_eq!(unconfirmed.len(), 1);
+    }
+}
@@ -0,0 +1,315 @@
+use futures::Future;
+
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::exchange::{ClassMethod, DeclareMethod, DeleteMethod, BindMethod,
+                                    UnbindMethod};
+
+use std::rc::Rc;
+
+use unsync::method::call_method_no_wait;
+use super::{Income, Outgo, LocalChannel, LocalChannelFuture, Recorded, Arguments};
+use errors::*;
+
+
+/// Type of an exchange.
+///
+/// Since 0.4.0, this is amqpr's own type instead of the re-export of
+/// `amqpr_api::exchange::declare::ExchangeType`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ExchangeType {
+    Direct,
+    Fanout,
+    Topic,
+    Headers,
+
+    /// Exchange type provided by a server plugin such as "x-delayed-message".
+    Custom(String),
+}
+
+
+impl ExchangeType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            &ExchangeType::Direct => "direct",
+            &ExchangeType::Fanout => "fanout",
+            &ExchangeType::Topic => "topic",
+            &ExchangeType::Headers => "headers",
+            &ExchangeType::Custom(ref typ) => typ.as_str(),
+        }
+    }
+}
+
+
+impl<'a> From<&'a str> for ExchangeType {
+    fn from(typ: &'a str) -> ExchangeType {
+        match typ {
+            "direct" => ExchangeType::Direct,
+            "fanout" => ExchangeType::Fanout,
+            "topic" => ExchangeType::Topic,
+            "headers" => ExchangeType::Headers,
+            custom => ExchangeType::Custom(custom.into()),
+        }
+    }
+}
+
+
+/// Option of `Exchange.Declare`.
+///
+/// Since 0.4.0, this is amqpr's own type with `arguments`, instead of the re-export of
+/// `amqpr_api::exchange::declare::DeclareExchangeOption`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DeclareExchangeOption {
+    pub name: String,
+    pub typ: ExchangeType,
+    pub is_passive: bool,
+    pub is_durable: bool,
+    pub is_auto_delete: bool,
+    pub is_internal: bool,
+    pub is_no_wait: bool,
+    pub arguments: Arguments,
+}
+
+
+/// Option of `Exchange.Bind` and `Exchange.Unbind`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BindExchangeOption {
+    /// Exchange which receives routed messages.
+    pub destination: String,
+
+    /// Exchange from which messages are routed.
+    pub source: String,
+
+    pub routing_key: String,
+    pub is_no_wait: bool,
+    pub arguments: Arguments,
+}
+
+
+
+pub fn declare_exchange<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: DeclareExchangeOption,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let record = match option.is_passive {
+        false => Some(Recorded::Exchange(option.clone())),
+        true => None,
+    };
+    let method = MethodPayload::Exchange(ClassMethod::Declare(DeclareMethod {
+        reserved1: 0,
+        exchange: option.name,
+        typ: option.typ.as_str().into(),
+        passive: option.is_passive,
+        durable: option.is_durable,
+        auto_delete: option.is_auto_delete,
+        internal: option.is_internal,
+        no_wait: option.is_no_wait,
+        arguments: option.arguments.into_table(),
+    }));
+
+    let no_wait = option.is_no_wait;
+    let declared = call_method_no_wait(ch.income, ch.outgo, ch_id, method, no_wait, |frame| {
+        frame.method().and_then(|c| c.exchange()).and_then(
+            |m| m.declare_ok(),
+        ).map(|_| ())
+    });
+
+    let fut = declared.map(move |(_, income, outgo)| {
+        if let (Some(recorder), Some(record)) = (recorder.as_ref(), record) {
+            recorder.record(record);
+        }
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn delete_exchange<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    exchange: String,
+    if_unused: bool,
+    no_wait: bool,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Exchange(ClassMethod::Delete(DeleteMethod {
+        reserved1: 0,
+        exchange: exchange.clone(),
+        if_unused: if_unused,
+        no_wait: no_wait,
+    }));
+
+    let deleted = call_method_no_wait(ch.income, ch.outgo, ch_id, method, no_wait, |frame| {
+        frame.method().and_then(|c| c.exchange()).and_then(
+            |m| m.delete_ok(),
+        ).map(|_| ())
+    });
+
+    let exchange2 = exchange.clone();
+    let fut = deleted.map_err(move |e| in_use_error(e, exchange2, if_unused)).map(
+        move |(_, income, outgo)| {
+            if let Some(ref recorder) = recorder {
+                recorder.remove_exchange(&exchange);
+            }
+            LocalChannel {
+                channel_id: ch_id,
+                income: income,
+                outgo: outgo,
+                recorder: recorder,
+                channel: channel,
+            }
+        },
+    );
+
+    Box::new(fut)
+}
+
+
+pub fn bind_exchange<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: BindExchangeOption,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Exchange(ClassMethod::Bind(BindMethod {
+        reserved1: 0,
+        destination: option.destination.clone(),
+        source: option.source.clone(),
+        routing_key: option.routing_key.clone(),
+        no_wait: option.is_no_wait,
+        arguments: option.arguments.clone().into_table(),
+    }));
+
+    let bound = call_method_no_wait(
+        ch.income,
+        ch.outgo,
+        ch_id,
+        method,
+        option.is_no_wait,
+        |frame| {
+            frame.method().and_then(|c| c.exchange()).and_then(
+                |m| m.bind_ok(),
+            ).map(|_| ())
+        },
+    );
+
+    let fut = bound.map(move |(_, income, outgo)| {
+        if let Some(ref recorder) = recorder {
+            recorder.record(Recorded::ExchangeBinding(option));
+        }
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn unbind_exchange<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: BindExchangeOption,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Exchange(ClassMethod::Unbind(UnbindMethod {
+        reserved1: 0,
+        destination: option.destination.clone(),
+        source: option.source.clone(),
+        routing_key: option.routing_key.clone(),
+        no_wait: option.is_no_wait,
+        arguments: option.arguments.clone().into_table(),
+    }));
+
+    let unbound = call_method_no_wait(
+        ch.income,
+        ch.outgo,
+        ch_id,
+        method,
+        option.is_no_wait,
+        |frame| {
+            frame.method().and_then(|c| c.exchange()).and_then(
+                |m| m.unbind_ok(),
+            ).map(|_| ())
+        },
+    );
+
+    let fut = unbound.map(move |(_, income, outgo)| {
+        if let Some(ref recorder) = recorder {
+            recorder.remove_exchange_binding(&option);
+        }
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
+
+
+/// Replace 406 (PRECONDITION_FAILED) by `ExchangeInUse` if the exchange is deleted with
+/// `if_unused`. Other errors are returned as they are.
+fn in_use_error(e: Rc<Error>, exchange: String, if_unused: bool) -> Rc<Error> {
+    let in_use = match find::<ClosedByServer>(&e) {
+        Some(closed) if if_unused && closed.reply_code == reply_code::PRECONDITION_FAILED => {
+            Some(ExchangeInUse {
+                exchange: exchange,
+                closed_by_server: closed.clone(),
+            })
+        }
+        _ => None,
+    };
+    match in_use {
+        Some(in_use) => Rc::new(Error::from(in_use)),
+        None => e,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed_by_server(reply_code: u16) -> Rc<Error> {
+        Rc::new(Error::from(ClosedByServer {
+            channel_id: 1,
+            reply_code: reply_code,
+            reply_text: "PRECONDITION_FAILED - exchange in use".into(),
+            class_id: 40,
+            method_id: 20,
+        }))
+    }
+
+
+    #[test]
+    fn precondition_failed_with_if_unused_is_in_use() {
+        let e = in_use_error(closed_by_server(406), "logs".into(), true);
+        let in_use = find::<ExchangeInUse>(&e).unwrap();
+        assert_eq!(in_use.exchange, "logs");
+        assert_eq!(in_use.closed_by_server.reply_code, 406);
+    }
+
+
+    #[test]
+    fn keep_error_without_if_unused() {
+        let e = in_use_error(closed_by_server(406), "logs".into(), false);
+        assert!(find::<ExchangeInUse>(&e).is_none());
+        assert_eq!(find::<ClosedByServer>(&e).unwrap().reply_code, 406);
+
+        let e = in_use_error(closed_by_server(404), "logs".into(), true);
+        assert!(find::<ExchangeInUse>(&e).is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use futures::task::{self, Task};
+
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::channel::{ClassMethod, CloseMethod};
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use unsync::connection::Connection;
+use unsync::method::method_frame;
+use errors::*;
+
+
+/// State of a local channel shared by `LocalChannel` and every `PublishSink` and
+/// `SubscribeStream` derived from it.
+/// When the last one is dropped without `LocalChannel::close`, `Channel.Close` is sent in
+/// background. Channel id is released when the last one is dropped and server has forgotten
+/// the channel, i.e. `Channel.Close-Ok` is exchanged, so that a new channel with the same id
+/// does not conflict with it.
+pub(crate) struct ChannelHandle {
+    pub(crate) id: u16,
+    pub(crate) conn: Rc<Connection>,
+    closed: Cell<bool>,
+
+    // True if server has forgotten this channel.
+    forgotten: Cell<bool>,
+
+    // Set if this channel is closed by server.
+    error: RefCell<Option<Rc<Error>>>,
+
+    // Tasks waiting for this channel to be closed.
+    close_waiters: RefCell<Vec<Task>>,
+}
+
+
+impl ChannelHandle {
+    pub(crate) fn new(id: u16, conn: Rc<Connection>) -> Rc<ChannelHandle> {
+        let handle = Rc::new(ChannelHandle {
+            id: id,
+            conn: conn,
+            closed: Cell::new(false),
+            forgotten: Cell::new(false),
+            error: RefCell::new(None),
+            close_waiters: RefCell::new(Vec::new()),
+        });
+        handle.conn.register_handle(&handle);
+        handle
+    }
+
+
+    /// Returns true if `Channel.Close` is started.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.get()
+    }
+
+
+    pub(crate) fn set_closed(&self) {
+        self.closed.set(true);
+        for task in self.close_waiters.borrow_mut().drain(..) {
+            task.notify();
+        }
+    }
+
+
+    /// Mark this channel as closed by server's `Channel.Close`.
+    pub(crate) fn set_closed_by_server(&self, e: Rc<Error>) {
+        *self.error.borrow_mut() = Some(e);
+        self.set_forgotten();
+        for task in self.close_waiters.borrow_mut().drain(..) {
+            task.notify();
+        }
+    }
+
+
+    /// Mark this channel as closed and unknown to server (e.g. `Channel.Close-Ok` is received).
+    /// Its id is released as soon as the last handle is dropped.
+    pub(crate) fn set_forgotten(&self) {
+        self.forgotten.set(true);
+        self.set_closed();
+    }
+
+
+    /// Returns an error if this channel is closed by server. Connection is not checked.
+    pub(crate) fn channel_error(&self) -> Option<Rc<Error>> {
+        self.error.borrow().clone()
+    }
+
+
+    /// Returns an error if this channel or the connection is closed by server.
+    pub(crate) fn error(&self) -> Option<Rc<Error>> {
+        self.error.borrow().clone().or_else(|| self.conn.error())
+    }
+
+
+    /// Notify current task when this channel is closed.
+    pub(crate) fn notify_on_close(&self) {
+        let mut waiters = self.close_waiters.borrow_mut();
+        if !waiters.iter().any(|t| t.will_notify_current()) {
+            waiters.push(task::current());
+        }
+    }
+
+
+    /// Returns an error if either this channel or the connection is closing.
+    pub(crate) fn check_open(&self) -> Result<(), Rc<Error>> {
+        self.conn.check_open()?;
+        if let Some(e) = self.error.borrow().clone() {
+            return Err(e);
+        }
+        match self.is_closed() {
+            true => Err(Rc::new(Error::from(ChannelClosed { channel_id: self.id }))),
+            false => Ok(()),
+        }
+    }
+}
+
+
+impl Drop for ChannelHandle {
+    fn drop(&mut self) {
+        self.conn.unregister_handle(self.id);
+        if self.forgotten.get() || self.conn.is_closed() {
+            self.conn.release_channel(self.id);
+            return;
+        }
+        if !self.is_closed() && !self.conn.is_closing() {
+            debug!("Every handle of channel {} is dropped. Send Channel.Close", self.id);
+            let frame = method_frame(self.id, close_method(200, "Channel is dropped".into()));
+            self.conn.send_frame(frame);
+        }
+        self.conn.release_channel_on_close_ok(self.id);
+    }
+}
+
+
+pub(crate) fn close_method(reply_code: u16, reply_text: String) -> MethodPayload {
+    MethodPayload::Channel(ClassMethod::Close(CloseMethod {
+        reply_code: reply_code,
+        reply_text: reply_text,
+        class_id: 0,
+        method_id: 0,
+    }))
+}
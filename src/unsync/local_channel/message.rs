@@ -0,0 +1,326 @@
+use amqpr_codec::content_header::Properties;
+
+use bytes::Bytes;
+
+use super::{Arguments, PublishOption};
+
+
+/// An item to publish with its properties sent in the content header.
+///
+/// ```ignore
+/// let message = Message::new(body)
+///     .content_type("application/json")
+///     .persistent()
+///     .correlation_id(request_id);
+/// ```
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Message {
+    pub body: Bytes,
+    pub properties: BasicProperties,
+}
+
+
+/// `Message` with its own routing key, and optionally exchange.
+/// A single sink created by `LocalChannel::routed_sink` can publish them with various routing
+/// keys, such as to a topic exchange.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RoutedMessage {
+    /// `None` means the exchange given on creation of the sink.
+    pub exchange: Option<String>,
+    pub routing_key: String,
+    pub message: Message,
+}
+
+
+/// Item which `PublishSink` accepts.
+pub trait PublishItem {
+    /// Split into a message and the option to publish it with.
+    /// `option` is the one given on creation of the sink.
+    fn into_publish(self, option: &PublishOption) -> (Message, PublishOption);
+}
+
+
+/// Properties of `Basic` content header. `None` properties are not sent.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct BasicProperties {
+    /// MIME content type such as "application/json".
+    pub content_type: Option<String>,
+
+    /// MIME content encoding such as "gzip".
+    pub content_encoding: Option<String>,
+
+    /// Application headers. Headers exchange routes messages by them.
+    pub headers: Option<Arguments>,
+
+    pub delivery_mode: Option<DeliveryMode>,
+
+    /// 0 to 9.
+    pub priority: Option<u8>,
+
+    /// Usually used to correlate a reply with a request.
+    pub correlation_id: Option<String>,
+
+    /// Usually used to name a queue to which a reply is sent.
+    pub reply_to: Option<String>,
+
+    /// Per-message TTL in milliseconds as a string, as RabbitMQ requires.
+    pub expiration: Option<String>,
+
+    pub message_id: Option<String>,
+
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+
+    /// Message type name. Named `typ` because `type` is a keyword.
+    pub typ: Option<String>,
+
+    /// RabbitMQ validates it against the user of the connection.
+    pub user_id: Option<String>,
+
+    pub app_id: Option<String>,
+
+    /// Deprecated by AMQP 0-9-1. You should not set it.
+    pub cluster_id: Option<String>,
+}
+
+
+/// Value of `delivery_mode` property.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeliveryMode {
+    /// Message may be lost if server restarts.
+    Transient,
+
+    /// Message is written to disk if it is routed to a durable queue.
+    Persistent,
+}
+
+
+
+impl Message {
+    pub fn new<B: Into<Bytes>>(body: B) -> Message {
+        Message {
+            body: body.into(),
+            properties: BasicProperties::default(),
+        }
+    }
+
+
+    pub fn content_type<S: Into<String>>(mut self, content_type: S) -> Message {
+        self.properties.content_type = Some(content_type.into());
+        self
+    }
+
+
+    pub fn content_encoding<S: Into<String>>(mut self, content_encoding: S) -> Message {
+        self.properties.content_encoding = Some(content_encoding.into());
+        self
+    }
+
+
+    pub fn headers(mut self, headers: Arguments) -> Message {
+        self.properties.headers = Some(headers);
+        self
+    }
+
+
+    pub fn delivery_mode(mut self, delivery_mode: DeliveryMode) -> Message {
+        self.properties.delivery_mode = Some(delivery_mode);
+        self
+    }
+
+
+    /// Same with `delivery_mode(DeliveryMode::Persistent)`.
+    pub fn persistent(self) -> Message {
+        self.delivery_mode(DeliveryMode::Persistent)
+    }
+
+
+    pub fn priority(mut self, priority: u8) -> Message {
+        self.properties.priority = Some(priority);
+        self
+    }
+
+
+    pub fn correlation_id<S: Into<String>>(mut self, correlation_id: S) -> Message {
+        self.properties.correlation_id = Some(correlation_id.into());
+        self
+    }
+
+
+    pub fn reply_to<S: Into<String>>(mut self, reply_to: S) -> Message {
+        self.properties.reply_to = Some(reply_to.into());
+        self
+    }
+
+
+    /// Per-message TTL in milliseconds.
+    pub fn expiration(mut self, millis: u64) -> Message {
+        self.properties.expiration = Some(millis.to_string());
+        self
+    }
+
+
+    pub fn message_id<S: Into<String>>(mut self, message_id: S) -> Message {
+        self.properties.message_id = Some(message_id.into());
+        self
+    }
+
+
+    pub fn timestamp(mut self, secs: u64) -> Message {
+        self.properties.timestamp = Some(secs);
+        self
+    }
+
+
+    pub fn typ<S: Into<String>>(mut self, typ: S) -> Message {
+        self.properties.typ = Some(typ.into());
+        self
+    }
+
+
+    pub fn user_id<S: Into<String>>(mut self, user_id: S) -> Message {
+        self.properties.user_id = Some(user_id.into());
+        self
+    }
+
+
+    pub fn app_id<S: Into<String>>(mut self, app_id: S) -> Message {
+        self.properties.app_id = Some(app_id.into());
+        self
+    }
+}
+
+
+impl RoutedMessage {
+    pub fn new<S, M>(routing_key: S, message: M) -> RoutedMessage
+    where
+        S: Into<String>,
+        M: Into<Message>,
+    {
+        RoutedMessage {
+            exchange: None,
+            routing_key: routing_key.into(),
+            message: message.into(),
+        }
+    }
+
+
+    pub fn exchange<S: Into<String>>(mut self, exchange: S) -> RoutedMessage {
+        self.exchange = Some(exchange.into());
+        self
+    }
+}
+
+
+
+impl PublishItem for Bytes {
+    fn into_publish(self, option: &PublishOption) -> (Message, PublishOption) {
+        (Message::new(self), option.clone())
+    }
+}
+
+
+impl PublishItem for Message {
+    fn into_publish(self, option: &PublishOption) -> (Message, PublishOption) {
+        (self, option.clone())
+    }
+}
+
+
+impl PublishItem for RoutedMessage {
+    fn into_publish(self, option: &PublishOption) -> (Message, PublishOption) {
+        let option = PublishOption {
+            exchange: self.exchange.unwrap_or_else(|| option.exchange.clone()),
+            routing_key: self.routing_key,
+            is_mandatory: option.is_mandatory,
+            is_immediate: option.is_immediate,
+        };
+        (self.message, option)
+    }
+}
+
+
+
+impl From<Bytes> for Message {
+    fn from(body: Bytes) -> Message {
+        Message::new(body)
+    }
+}
+
+
+impl From<Vec<u8>> for Message {
+    fn from(body: Vec<u8>) -> Message {
+        Message::new(body)
+    }
+}
+
+
+
+impl BasicProperties {
+    pub(crate) fn from_properties(properties: &Properties) -> BasicProperties {
+        BasicProperties {
+            content_type: properties.content_type.clone(),
+            content_encoding: properties.content_encoding.clone(),
+            headers: properties.headers.clone().map(Arguments::from),
+            delivery_mode: properties.delivery_mode.and_then(|mode| match mode {
+                1 => Some(DeliveryMode::Transient),
+                2 => Some(DeliveryMode::Persistent),
+                _ => None,
+            }),
+            priority: properties.priority,
+            correlation_id: properties.correlation_id.clone(),
+            reply_to: properties.reply_to.clone(),
+            expiration: properties.expiration.clone(),
+            message_id: properties.message_id.clone(),
+            timestamp: properties.timestamp,
+            typ: properties.type_.clone(),
+            user_id: properties.user_id.clone(),
+            app_id: properties.app_id.clone(),
+            cluster_id: properties.cluster_id.clone(),
+        }
+    }
+
+
+    /// Rough size of encoded properties.
+    pub(crate) fn estimated_size(&self) -> usize {
+        let strings = [
+            &self.content_type,
+            &self.content_encoding,
+            &self.correlation_id,
+            &self.reply_to,
+            &self.expiration,
+            &self.message_id,
+            &self.typ,
+            &self.user_id,
+            &self.app_id,
+            &self.cluster_id,
+        ];
+        // A short string has 1 byte length.
+        let strings: usize = strings.iter().filter_map(|s| s.as_ref()).map(|s| 1 + s.len()).sum();
+        let headers = self.headers.as_ref().map(|h| h.estimated_size()).unwrap_or(0);
+        strings + headers
+    }
+
+
+    pub(crate) fn into_properties(self) -> Properties {
+        let mut properties = Properties::new();
+        properties.content_type = self.content_type;
+        properties.content_encoding = self.content_encoding;
+        properties.headers = self.headers.map(|headers| headers.into_table());
+        properties.delivery_mode = self.delivery_mode.map(|mode| match mode {
+            DeliveryMode::Transient => 1,
+            DeliveryMode::Persistent => 2,
+        });
+        properties.priority = self.priority;
+        properties.correlation_id = self.correlation_id;
+        properties.reply_to = self.reply_to;
+        properties.expiration = self.expiration;
+        properties.message_id = self.message_id;
+        properties.timestamp = self.timestamp;
+        properties.type_ = self.typ;
+        properties.user_id = self.user_id;
+        properties.app_id = self.app_id;
+        properties.cluster_id = self.cluster_id;
+        properties
+    }
+}
@@ -1,27 +1,44 @@
+mod message;
 mod publish;
+mod confirm;
+mod returned;
 mod subscribe;
-
-pub use self::publish::{PublishFuture, PublishSink, PublishOption};
+mod qos;
+mod arguments;
+mod exchange;
+mod queue;
+mod recorder;
+mod handle;
+
+pub use self::message::{Message, RoutedMessage, PublishItem, BasicProperties, DeliveryMode};
+pub use self::publish::{PublishFuture, PublishSink, PublishOption, DEFAULT_HIGH_WATER_MARK};
+pub use self::confirm::{ConfirmPublishSink, ConfirmSinkFuture, Confirmation};
+pub use self::returned::{ReturnedStream, ReturnedMessage};
 pub use self::subscribe::{SubscribeStream, SubscribeOption};
-pub use amqpr_api::exchange::declare::ExchangeType;
+pub use self::qos::QosOption;
+pub use self::arguments::{Arguments, HeadersMatch, Overflow, QueueType};
+pub use self::exchange::{ExchangeType, DeclareExchangeOption, BindExchangeOption};
+pub use self::queue::{MessageCountFuture, QueueInfoFuture, QueueInfo, DeclareQueueOption,
+                      BindQueueOption, DeleteQueueOption, PurgeQueueOption, UnbindQueueOption};
+pub use self::recorder::{Recorder, Recorded};
+pub(crate) use self::handle::ChannelHandle;
 
 use futures::Future;
+use futures::future;
 
 use ex_futures::sink::{SinkExt, UnsyncCloneable};
 
 use bytes::Bytes;
 
-use amqpr_api::exchange::declare::{declare_exchange_wait, DeclareExchangeOption};
-use amqpr_api::queue::declare::{declare_queue_wait, DeclareQueueOption};
-use amqpr_api::queue::bind::{bind_queue_wait, BindQueueOption};
-
 use std::rc::Rc;
 
-use super::{Income, Outgo, BoxedIncome};
+use super::{Income, Outgo, BoxedIncome, AmqpFuture};
+use super::method::call_method;
+use self::handle::close_method;
 use errors::*;
 
 
-type LocalChannelFuture<In, Out> = Box<Future<Item = LocalChannel<In, Out>, Error = Rc<Error>>>;
+pub(crate) type LocalChannelFuture<In, Out> = Box<Future<Item = LocalChannel<In, Out>, Error = Rc<Error>>>;
 
 type QueueName = String;
 type DeclareQueueFuture<In, Out> = Box<
@@ -40,10 +57,59 @@ pub struct LocalChannel<In: Income, Out: Outgo> {
 
     // Sink of any kind of Frame. Should we restrict it?
     pub(crate) outgo: Out,
+
+    // Records topology declared through this channel if it is set.
+    pub(crate) recorder: Option<Recorder>,
+
+    // Shared with `PublishSink` and `SubscribeStream` derived from this channel.
+    pub(crate) channel: Rc<ChannelHandle>,
 }
 
 
 impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
+    /// Start recording topology (exchanges, queues, bindings and QoS) declared through this
+    /// channel. Passive declarations are not recorded.
+    pub fn record(mut self, recorder: Recorder) -> LocalChannel<In, Out> {
+        self.recorder = Some(recorder);
+        self
+    }
+
+
+    /// Returns recorder set by `record` function.
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+
+
+    /// Close this channel by `Channel.Close` and wait for `Channel.Close-Ok`.
+    ///
+    /// Once it is started, `PublishSink` and `SubscribeStream` derived from this channel stop
+    /// working. Items which are not flushed yet may be lost.
+    ///
+    /// If every handle of this channel (`LocalChannel`, `PublishSink` and `SubscribeStream`) is
+    /// dropped without calling this function, `Channel.Close` is sent in background.
+    pub fn close<S: Into<String>>(self, reply_code: u16, reason: S) -> Box<AmqpFuture<()>> {
+        let channel = self.channel;
+        if let Err(e) = channel.check_open() {
+            return Box::new(future::err(e));
+        }
+        channel.set_closed();
+
+        let close = close_method(reply_code, reason.into());
+        let fut = call_method(self.income, self.outgo, channel.id, close, |frame| {
+            frame.method().and_then(|c| c.channel()).and_then(
+                |m| m.close_ok(),
+            ).map(|_| ())
+        }).map(move |((), _income, _outgo)| {
+            debug!("Channel {} is closed", channel.id);
+            channel.set_forgotten();
+        });
+
+        Box::new(fut)
+    }
+
+
     /// Declare an exchange on AMQP server using default option.
     /// # Option
     /// - is_passive: false
@@ -51,6 +117,7 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
     /// - is_auto_delete: false
     /// - is_internal: false
     /// - is_no_wait: false
+    /// - arguments: empty
     pub fn declare_exchange<T>(self, exchange: T, typ: ExchangeType) -> LocalChannelFuture<In, Out>
     where
         T: Into<String>,
@@ -63,6 +130,7 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
             is_auto_delete: false,
             is_internal: false,
             is_no_wait: false,
+            arguments: Arguments::new(),
         };
 
         self.declare_exchange_with_option(option)
@@ -70,21 +138,33 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
 
 
     /// Declare an exchange on AMQP server with option.
+    /// If `is_no_wait` is true, returned future completes as soon as the method is sent, and
+    /// any failure is reported by closing this channel.
     pub fn declare_exchange_with_option(
         self,
         option: DeclareExchangeOption,
     ) -> LocalChannelFuture<In, Out> {
-        let channel_id = self.channel_id;
-        let declared = declare_exchange_wait(self.income, self.outgo, self.channel_id, option);
-        let fut = declared.map(move |(income, outgo)| {
-            LocalChannel {
-                channel_id: channel_id,
-                income: income,
-                outgo: outgo,
-            }
-        });
+        self::exchange::declare_exchange(self, option)
+    }
 
-        Box::new(fut)
+
+
+    /// Delete an exchange on AMQP server.
+    ///
+    /// If `if_unused` is true and the exchange still has bindings, returned future fails with
+    /// `ExchangeInUse` error and this channel is closed by server.
+    /// If `no_wait` is true, returned future completes without waiting for `Exchange.Delete-Ok`.
+    /// An error is reported by the next operation on this channel in that case.
+    pub fn delete_exchange<T>(
+        self,
+        exchange: T,
+        if_unused: bool,
+        no_wait: bool,
+    ) -> LocalChannelFuture<In, Out>
+    where
+        T: Into<String>,
+    {
+        self::exchange::delete_exchange(self, exchange.into(), if_unused, no_wait)
     }
 
 
@@ -93,6 +173,8 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
     /// connection, and are deleted when that connection close.
     /// You MAY NOT attempt to use a queue that was declared as private by another still-open
     /// connection.
+    /// If `name` is empty, server generates a name and returned future yields it. Consider to
+    /// use `declare_anonymous_queue` in that case.
     pub fn declare_private_queue<T>(self, name: T) -> DeclareQueueFuture<In, Out>
     where
         T: Into<String>,
@@ -104,31 +186,76 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
             is_exclusive: true,
             is_auto_delete: false,
             is_no_wait: false,
+            arguments: Arguments::new(),
         };
 
         self.declare_queue_with_option(option)
     }
 
 
+    /// Declare an exclusive and auto delete queue whose name is generated by server.
+    /// Returned `QueueInfo` can be passed to `bind_queue` and `subscribe_stream` as a queue name.
+    ///
+    /// ```ignore
+    /// local.declare_anonymous_queue().and_then(|(queue, local)| {
+    ///     local.bind_queue(&queue, "logs", "").map(|local| local.subscribe_stream(queue, "tag"))
+    /// })
+    /// ```
+    pub fn declare_anonymous_queue(self) -> QueueInfoFuture<In, Out> {
+        let option = DeclareQueueOption {
+            name: "".into(),
+            is_passive: false,
+            is_durable: false,
+            is_exclusive: true,
+            is_auto_delete: true,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+
+        self.declare_queue_info_with_option(option)
+    }
+
+
 
     /// Declare a queue on AMQP server with option.
+    /// If `is_no_wait` is true, returned future completes as soon as the method is sent and
+    /// yields the requested name. It fails with `InvalidInput` error if the name is empty
+    /// (server named queue), because server does not tell the generated name.
     pub fn declare_queue_with_option(
         self,
         option: DeclareQueueOption,
     ) -> DeclareQueueFuture<In, Out> {
-        let ch_id = self.channel_id;
-        // TODO : switch by no_wait flag
-        let declared = declare_queue_wait(self.income, self.outgo, self.channel_id, option);
-        let fut = declared.map(move |(res, income, outgo)| {
-            let ch = LocalChannel {
-                channel_id: ch_id,
-                income: income,
-                outgo: outgo,
-            };
-            (res.queue, ch)
-        });
+        self::queue::declare_queue(self, option)
+    }
 
-        Box::new(fut)
+
+    /// Same with `declare_queue_with_option` but returns `QueueInfo` which has message count
+    /// and consumer count. Both counts are `None` if `is_no_wait` is true.
+    pub fn declare_queue_info_with_option(
+        self,
+        option: DeclareQueueOption,
+    ) -> QueueInfoFuture<In, Out> {
+        self::queue::declare_queue_info(self, option)
+    }
+
+
+    /// Get `QueueInfo` of an existing queue by passive declaration.
+    /// If the queue does not exist, returned future fails with `ClosedByServer` error
+    /// (404 NOT_FOUND) and this channel is closed.
+    pub fn inspect_queue<T>(self, name: T) -> QueueInfoFuture<In, Out>
+    where
+        T: Into<String>,
+    {
+        let option = DeclareQueueOption {
+            name: name.into(),
+            is_passive: true,
+            is_durable: false,
+            is_exclusive: false,
+            is_auto_delete: false,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+        self.declare_queue_info_with_option(option)
     }
 
 
@@ -149,32 +276,221 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
             exchange: exchange.into(),
             routing_key: routing_key.into(),
             is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+        self.bind_queue_with_option(option)
+    }
+
+
+    /// Bind a queue to headers exchange. Messages whose headers match `headers` according to
+    /// `x_match` are routed to the queue.
+    ///
+    /// ```ignore
+    /// let headers = Arguments::new()
+    ///     .insert("format", FieldArgument::LongString("pdf".into()))
+    ///     .insert("type", FieldArgument::LongString("report".into()));
+    /// local.bind_queue_with_headers("reports", "by-attrs", HeadersMatch::All, headers)
+    /// ```
+    pub fn bind_queue_with_headers<S, T>(
+        self,
+        queue: S,
+        exchange: T,
+        x_match: HeadersMatch,
+        headers: Arguments,
+    ) -> LocalChannelFuture<In, Out>
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let option = BindQueueOption {
+            queue: queue.into(),
+            exchange: exchange.into(),
+            routing_key: "".into(),
+            is_no_wait: false,
+            arguments: headers.x_match(x_match),
         };
         self.bind_queue_with_option(option)
     }
 
 
     /// Bind a queue to AMQP servier with option.
+    /// If `is_no_wait` is true, returned future does not wait for `Queue.Bind-Ok`.
     pub fn bind_queue_with_option(self, option: BindQueueOption) -> LocalChannelFuture<In, Out> {
-        let ch_id = self.channel_id;
-        let bound = bind_queue_wait(self.income, self.outgo, self.channel_id, option);
-        let fut = bound.map(move |(income, outgo)| {
-            LocalChannel {
-                channel_id: ch_id,
-                income: income,
-                outgo: outgo,
-            }
-        });
+        self::queue::bind_queue(self, option)
+    }
 
-        Box::new(fut)
+
+
+    /// Remove a binding between a queue and an exchange.
+    pub fn unbind_queue<S, T, U>(
+        self,
+        queue: S,
+        exchange: T,
+        routing_key: U,
+    ) -> LocalChannelFuture<In, Out>
+    where
+        S: Into<String>,
+        T: Into<String>,
+        U: Into<String>,
+    {
+        let option = UnbindQueueOption {
+            queue: queue.into(),
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            arguments: Arguments::new(),
+        };
+        self.unbind_queue_with_option(option)
+    }
+
+
+    /// Remove a binding between a queue and an exchange with option.
+    pub fn unbind_queue_with_option(
+        self,
+        option: UnbindQueueOption,
+    ) -> LocalChannelFuture<In, Out> {
+        self::queue::unbind_queue(self, option)
+    }
+
+
+
+    /// Delete a queue unconditionally.
+    /// Returned future's item is the number of messages deleted with the queue.
+    pub fn delete_queue<S: Into<String>>(self, queue: S) -> MessageCountFuture<In, Out> {
+        let option = DeleteQueueOption {
+            queue: queue.into(),
+            if_unused: false,
+            if_empty: false,
+            is_no_wait: false,
+        };
+        self.delete_queue_with_option(option)
+    }
+
+
+    /// Delete a queue with option.
+    /// If the condition of `if_unused` or `if_empty` is not satisfied, returned future fails
+    /// with `ClosedByServer` error (406 PRECONDITION_FAILED) and this channel is closed.
+    pub fn delete_queue_with_option(
+        self,
+        option: DeleteQueueOption,
+    ) -> MessageCountFuture<In, Out> {
+        self::queue::delete_queue(self, option)
+    }
+
+
+
+    /// Remove all messages in a queue which are not awaiting acknowledgment.
+    /// Returned future's item is the number of purged messages.
+    pub fn purge_queue<S: Into<String>>(self, queue: S) -> MessageCountFuture<In, Out> {
+        let option = PurgeQueueOption {
+            queue: queue.into(),
+            is_no_wait: false,
+        };
+        self.purge_queue_with_option(option)
+    }
+
+
+    /// Purge a queue with option.
+    pub fn purge_queue_with_option(
+        self,
+        option: PurgeQueueOption,
+    ) -> MessageCountFuture<In, Out> {
+        self::queue::purge_queue(self, option)
+    }
+
+
+
+    /// Bind an exchange to another exchange. Messages published to `source` are routed to
+    /// `destination` if they match `routing_key`.
+    pub fn bind_exchange<S, T, U>(
+        self,
+        destination: S,
+        source: T,
+        routing_key: U,
+    ) -> LocalChannelFuture<In, Out>
+    where
+        S: Into<String>,
+        T: Into<String>,
+        U: Into<String>,
+    {
+        let option = BindExchangeOption {
+            destination: destination.into(),
+            source: source.into(),
+            routing_key: routing_key.into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+        self.bind_exchange_with_option(option)
+    }
+
+
+    /// Bind an exchange to another exchange with option.
+    pub fn bind_exchange_with_option(
+        self,
+        option: BindExchangeOption,
+    ) -> LocalChannelFuture<In, Out> {
+        self::exchange::bind_exchange(self, option)
+    }
+
+
+    /// Remove a binding between exchanges.
+    pub fn unbind_exchange<S, T, U>(
+        self,
+        destination: S,
+        source: T,
+        routing_key: U,
+    ) -> LocalChannelFuture<In, Out>
+    where
+        S: Into<String>,
+        T: Into<String>,
+        U: Into<String>,
+    {
+        let option = BindExchangeOption {
+            destination: destination.into(),
+            source: source.into(),
+            routing_key: routing_key.into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+        self.unbind_exchange_with_option(option)
+    }
+
+
+    /// Remove a binding between exchanges with option.
+    /// `option` must have the same routing key and arguments with the binding.
+    pub fn unbind_exchange_with_option(
+        self,
+        option: BindExchangeOption,
+    ) -> LocalChannelFuture<In, Out> {
+        self::exchange::unbind_exchange(self, option)
+    }
+
+
+
+    /// Limit the number of messages (or octets) which server delivers before they are
+    /// acknowledged.
+    pub fn qos(self, prefetch_count: u16) -> LocalChannelFuture<In, Out> {
+        let option = QosOption {
+            prefetch_size: 0,
+            prefetch_count: prefetch_count,
+            is_global: false,
+        };
+        self.qos_with_option(option)
+    }
+
+
+    /// Send `Basic.Qos` method with option.
+    pub fn qos_with_option(self, option: QosOption) -> LocalChannelFuture<In, Out> {
+        self::qos::qos(self, option)
     }
 
 
 
     /// Publish an item to exchange.
     /// Maybe it is more useful to use `publish_sink` function instead.
-    pub fn publish<S, T>(self, bytes: Bytes, exchange: S, routing_key: T) -> PublishFuture<In, Out>
+    /// `message` is `Bytes` or `Message` which has properties.
+    pub fn publish<M, S, T>(self, message: M, exchange: S, routing_key: T) -> PublishFuture<In, Out>
     where
+        M: Into<Message>,
         S: Into<String>,
         T: Into<String>,
     {
@@ -184,18 +500,29 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
             is_mandatory: false,
             is_immediate: false,
         };
-        self.publish_with_option(bytes, option)
+        self.publish_with_option(message, option)
     }
 
 
     /// Publish an item to exchange specified by option.
     /// Maybe it is more useful to use `publish_sink_with_option` function instead.
-    pub fn publish_with_option(
+    pub fn publish_with_option<M>(self, message: M, option: PublishOption) -> PublishFuture<In, Out>
+    where
+        M: Into<Message>,
+    {
+        self::publish::publish(self, message.into(), option)
+    }
+
+
+    /// Publish an item with headers. Headers exchange routes it by `headers` instead of
+    /// routing key. Same with publishing `Message` which has `headers` property.
+    pub fn publish_with_headers(
         self,
         bytes: Bytes,
         option: PublishOption,
+        headers: Arguments,
     ) -> PublishFuture<In, Out> {
-        self::publish::publish(self, bytes, option)
+        self.publish_with_option(Message::new(bytes).headers(headers), option)
     }
 
 
@@ -228,20 +555,173 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
         self,
         option: PublishOption,
     ) -> (LocalChannel<In, UnsyncCloneable<Out>>, PublishSink<UnsyncCloneable<Out>>) {
+        self.typed_publish_sink(option)
+    }
+
+
+    /// Get a outbound endpoint to publish `Message`s with default option.
+    /// # Option
+    /// - mandatory: false
+    /// - immediate: false
+    pub fn message_sink<S, T>(
+        self,
+        exchange: S,
+        routing_key: T,
+    ) -> (LocalChannel<In, UnsyncCloneable<Out>>, PublishSink<UnsyncCloneable<Out>, Message>)
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let option = PublishOption {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            is_mandatory: false,
+            is_immediate: false,
+        };
+        self.message_sink_with_option(option)
+    }
+
+
+    /// Get a outbound endpoint to publish `Message`s.
+    pub fn message_sink_with_option(
+        self,
+        option: PublishOption,
+    ) -> (LocalChannel<In, UnsyncCloneable<Out>>, PublishSink<UnsyncCloneable<Out>, Message>) {
+        self.typed_publish_sink(option)
+    }
+
+
+    /// Get a outbound endpoint to publish `RoutedMessage`s, which have their own routing keys,
+    /// to `exchange`.
+    pub fn routed_sink<S>(
+        self,
+        exchange: S,
+    ) -> (LocalChannel<In, UnsyncCloneable<Out>>,
+          PublishSink<UnsyncCloneable<Out>, RoutedMessage>)
+    where
+        S: Into<String>,
+    {
+        let option = PublishOption {
+            exchange: exchange.into(),
+            routing_key: "".into(),
+            is_mandatory: false,
+            is_immediate: false,
+        };
+        self.routed_sink_with_option(option)
+    }
+
+
+    /// Get a outbound endpoint to publish `RoutedMessage`s.
+    /// `routing_key` of `option` is ignored, and `exchange` of it is used if an item does not
+    /// have its own exchange.
+    pub fn routed_sink_with_option(
+        self,
+        option: PublishOption,
+    ) -> (LocalChannel<In, UnsyncCloneable<Out>>,
+          PublishSink<UnsyncCloneable<Out>, RoutedMessage>) {
+        self.typed_publish_sink(option)
+    }
+
+
+    fn typed_publish_sink<T: PublishItem>(
+        self,
+        option: PublishOption,
+    ) -> (LocalChannel<In, UnsyncCloneable<Out>>, PublishSink<UnsyncCloneable<Out>, T>) {
         let (id, income, outgo) = (self.channel_id, self.income, self.outgo);
         let cloneable_outgo = outgo.unsync_cloneable();
         let local_ch = LocalChannel {
             channel_id: id.clone(),
             income: income,
             outgo: cloneable_outgo.clone(),
+            recorder: self.recorder,
+            channel: self.channel.clone(),
         };
-        let pub_sink = self::publish::publish_sink(id, option, cloneable_outgo);
+        let pub_sink = self::publish::publish_sink(self.channel, option, cloneable_outgo);
         (local_ch, pub_sink)
     }
 
 
 
 
+    /// Get a stream of messages returned by server by `Basic.Return`. Server returns a message
+    /// published with `mandatory` if it is not routed to any queue.
+    /// `ConfirmPublishSink` handles returned messages by itself, so this stream is not needed
+    /// for it.
+    pub fn returned_stream(self) -> (LocalChannel<BoxedIncome, Out>, ReturnedStream) {
+        self::returned::returned_stream(self)
+    }
+
+
+
+    /// Put this channel into confirm mode by `Confirm.Select`. Server confirms every item
+    /// published after this by `Basic.Ack` or `Basic.Nack`.
+    /// Usually you should use `confirm_publish_sink` which handles them.
+    pub fn confirm_select(self) -> LocalChannelFuture<In, Out> {
+        self::confirm::confirm_select(self)
+    }
+
+
+    /// Put this channel into confirm mode and get a sink to publish items with default option.
+    /// # Option
+    /// - mandatory: false
+    /// - immediate: false
+    pub fn confirm_publish_sink<S, T>(
+        self,
+        exchange: S,
+        routing_key: T,
+    ) -> ConfirmSinkFuture<Out>
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let option = PublishOption {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            is_mandatory: false,
+            is_immediate: false,
+        };
+        self.confirm_publish_sink_with_option(option)
+    }
+
+
+    /// Put this channel into confirm mode and get a sink to publish items.
+    /// The sink is also a stream of `Confirmation`. It takes this channel over, because any
+    /// other publish on the channel would shift delivery tags of its items.
+    ///
+    /// ```ignore
+    /// local.confirm_publish_sink("logs", "").map(|sink| {
+    ///     let (sink, confirmations) = sink.split();
+    ///     ...
+    /// })
+    /// ```
+    pub fn confirm_publish_sink_with_option(
+        self,
+        option: PublishOption,
+    ) -> ConfirmSinkFuture<Out> {
+        self::confirm::confirm_publish_sink(self, option)
+    }
+
+
+    /// Same with `confirm_publish_sink_with_option` but the sink publishes `Message`s.
+    pub fn confirm_message_sink_with_option(
+        self,
+        option: PublishOption,
+    ) -> ConfirmSinkFuture<Out, Message> {
+        self::confirm::confirm_publish_sink(self, option)
+    }
+
+
+    /// Same with `confirm_publish_sink_with_option` but the sink publishes `RoutedMessage`s.
+    pub fn confirm_routed_sink_with_option(
+        self,
+        option: PublishOption,
+    ) -> ConfirmSinkFuture<Out, RoutedMessage> {
+        self::confirm::confirm_publish_sink(self, option)
+    }
+
+
+
+
     /// Get an inbound stream of subscribed items.
     /// The stream you get is private. It means that only single stream is available for single queue.
     /// If you want "shared" stream, please look into `subscribe_shared_stream` function.
@@ -255,13 +735,14 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
         S: Into<String>,
         T: Into<String>,
     {
-        self::subscribe::subscribe_stream(
-            self,
-            queue.into(),
-            consumer_tag.into(),
-            false, // no_local
-            true, // exclusibe
-        )
+        let option = SubscribeOption {
+            queue: queue.into(),
+            consumer_tag: consumer_tag.into(),
+            is_no_local: false,
+            is_exclusive: true,
+            arguments: Arguments::new(),
+        };
+        self.subscribe_stream_with_option(option)
     }
 
 
@@ -279,12 +760,25 @@ impl<In: Income, Out: Outgo> LocalChannel<In, Out> {
         S: Into<String>,
         T: Into<String>,
     {
-        self::subscribe::subscribe_stream(
-            self,
-            queue.into(),
-            consumer_tag.into(),
-            false, // no_local
-            false, // exclusibe
-        )
+        let option = SubscribeOption {
+            queue: queue.into(),
+            consumer_tag: consumer_tag.into(),
+            is_no_local: false,
+            is_exclusive: false,
+            arguments: Arguments::new(),
+        };
+        self.subscribe_stream_with_option(option)
+    }
+
+
+
+    /// Get an inbound stream of subscribed items with option such as consumer priority.
+    /// Stream is based on `no_ack` consume and always waits for `Basic.Consume-Ok`.
+    pub fn subscribe_stream_with_option(
+        self,
+        option: SubscribeOption,
+    ) -> (LocalChannel<BoxedIncome, UnsyncCloneable<Out>>,
+              SubscribeStream<BoxedIncome, UnsyncCloneable<Out>>) {
+        self::subscribe::subscribe_stream(self, option)
     }
 }
@@ -1,31 +1,79 @@
-use futures::{Sink, Future, Poll, StartSend, Async, AsyncSink};
+use futures::{Sink, Future, Poll, StartSend, Async, AsyncSink, stream};
 
 use ex_futures::util::Should;
 
-use amqpr_api::basic::publish::{publish as publish_, Published};
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{ClassMethod, PublishMethod};
+use amqpr_codec::content_header::ContentHeaderPayload;
+use amqpr_codec::content_body::ContentBodyPayload;
 
 use bytes::Bytes;
 
+use std::cmp;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem;
 use std::rc::Rc;
 
-use super::{Income, Outgo, LocalChannel};
+use super::{Income, Outgo, LocalChannel, Recorder, Message, PublishItem};
+use unsync::connection::PublishGuard;
+use unsync::method::method_frame;
+use super::ChannelHandle;
 use errors::*;
 
 
-pub use amqpr_api::basic::publish::PublishOption;
+/// Option of `Basic.Publish`.
+///
+/// Since 0.4.0, this is amqpr's own type instead of the re-export of
+/// `amqpr_api::basic::publish::PublishOption`. It has the same fields.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PublishOption {
+    pub exchange: String,
+    pub routing_key: String,
+    pub is_mandatory: bool,
+    pub is_immediate: bool,
+}
+
+
+/// Default high-water mark of `PublishSink` in bytes.
+pub const DEFAULT_HIGH_WATER_MARK: usize = 64 * 1024;
+
+
+const BASIC_CLASS_ID: u16 = 60;
+
+// Frame header (7 bytes) and frame end (1 byte).
+const FRAME_OVERHEAD: usize = 8;
+
+// Rough size of `Basic.Publish` and a content header except exchange, routing key and
+// properties.
+const FIXED_FIELDS_SIZE: usize = 64;
+
+
+type Published<Out> = Box<Future<Item = Out, Error = Rc<Error>>>;
 
 
 
 pub fn publish<In: Income, Out: Outgo>(
     ch: LocalChannel<In, Out>,
-    bytes: Bytes,
+    message: Message,
     option: PublishOption,
 ) -> PublishFuture<In, Out> {
     let (ch_id, income, outgo) = (ch.channel_id, ch.income, ch.outgo);
-    let published = publish_(outgo, ch_id, bytes, option);
+
+    // Take a guard now so that `GlobalChannel::close` waits for this even before it is polled.
+    let (guard, error) = match ch.channel.check_open() {
+        Ok(()) => (Some(PublishGuard::new(&ch.channel.conn)), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    let published = publish_(outgo, &ch.channel, message, &option);
     PublishFuture {
-        store: Should::new((ch_id, income)),
+        store: Should::new((ch_id, income, ch.recorder)),
         published: published,
+        channel: ch.channel,
+        guard: guard,
+        error: error,
     }
 }
 
@@ -33,8 +81,13 @@ pub fn publish<In: Income, Out: Outgo>(
 
 /// Future which will return `LocalChannel` when complete to publish bytes.
 pub struct PublishFuture<In: Income, Out: Outgo> {
-    store: Should<(u16, In)>,
+    store: Should<(u16, In, Option<Recorder>)>,
     published: Published<Out>,
+    channel: Rc<ChannelHandle>,
+    guard: Option<PublishGuard>,
+
+    // Set if the channel is already closing when this is created.
+    error: Option<Rc<Error>>,
 }
 
 
@@ -43,12 +96,19 @@ impl<In: Income, Out: Outgo> Future for PublishFuture<In, Out> {
     type Error = Rc<Error>;
 
     fn poll(&mut self) -> Poll<LocalChannel<In, Out>, Rc<Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
         let outgo = try_ready!(self.published.poll());
-        let (ch_id, income) = self.store.take();
+        self.guard = None;
+        let (ch_id, income, recorder) = self.store.take();
         Ok(Async::Ready(LocalChannel {
             channel_id: ch_id,
             income: income,
             outgo: outgo,
+            recorder: recorder,
+            channel: self.channel.clone(),
         }))
     }
 }
@@ -56,68 +116,318 @@ impl<In: Income, Out: Outgo> Future for PublishFuture<In, Out> {
 
 
 
-pub fn publish_sink<Out: Outgo>(
-    channel: u16,
+pub(crate) fn publish_sink<Out: Outgo, T: PublishItem>(
+    channel: Rc<ChannelHandle>,
     option: PublishOption,
     sink: Out,
-) -> PublishSink<Out> {
+) -> PublishSink<Out, T> {
     PublishSink {
         channel: channel,
         option: option,
-        state: PublishState::Waiting(Should::new(sink)),
+        sink: Some(sink),
+        buffer: VecDeque::new(),
+        buffered_bytes: 0,
+        high_water_mark: DEFAULT_HIGH_WATER_MARK,
+        guard: None,
+        item: PhantomData,
     }
 }
 
 
 /// A outbound endpoint to publish data.
-pub struct PublishSink<Out: Outgo> {
-    channel: u16,
+/// Items are `Bytes` by default. `PublishSink<Out, Message>` publishes items with properties
+/// and `PublishSink<Out, RoutedMessage>` publishes items with their own routing keys.
+/// After `GlobalChannel::close` or `LocalChannel::close` is started, it does not accept any
+/// more item.
+/// While server blocks the connection by `Connection.Blocked`, `start_send` returns `NotReady`.
+///
+/// Frames of items are written into the write buffer of the connection without flushing, so
+/// many items are sent by a single syscall. `start_send` returns `NotReady` only if unflushed
+/// bytes exceed the high-water mark (`DEFAULT_HIGH_WATER_MARK` by default) and they can not be
+/// flushed yet. Items are not flushed until `poll_complete` is called, e.g. by `send_all` or
+/// `flush`.
+///
+/// If the sink is dropped before it is flushed, frames of accepted items are flushed in
+/// background so that no message is cut in the middle. `GlobalChannel::close` waits for them.
+pub struct PublishSink<Out: Outgo, T: PublishItem = Bytes> {
+    channel: Rc<ChannelHandle>,
     option: PublishOption,
-    state: PublishState<Out>,
-}
 
+    // Taken only on drop.
+    sink: Option<Out>,
+
+    // Frames which `sink` has not accepted yet.
+    buffer: VecDeque<Frame>,
+
+    // Approximate size of frames which are not flushed yet, including ones in `buffer`.
+    buffered_bytes: usize,
+
+    high_water_mark: usize,
 
-enum PublishState<Out: Outgo> {
-    Processing(Published<Out>),
-    Waiting(Should<Out>),
+    // `PublishGuard` lets `GlobalChannel::close` wait until buffered frames are flushed.
+    guard: Option<PublishGuard>,
+
+    item: PhantomData<T>,
 }
 
 
-impl<Out: Outgo> Sink for PublishSink<Out> {
-    type SinkItem = Bytes;
-    type SinkError = Rc<Error>;
+impl<Out: Outgo, T: PublishItem> PublishSink<Out, T> {
+    pub(crate) fn option(&self) -> &PublishOption {
+        &self.option
+    }
+
+
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+
+    /// Set the number of unflushed bytes above which `start_send` tries to flush them before
+    /// accepting a new item. 0 means flushing every item.
+    pub fn set_high_water_mark(&mut self, bytes: usize) {
+        self.high_water_mark = bytes;
+    }
 
-    fn start_send(&mut self, bytes: Bytes) -> StartSend<Bytes, Self::SinkError> {
 
-        if let Async::NotReady = self.poll_complete()? {
-            return Ok(AsyncSink::NotReady(bytes));
+    /// `Ready` if `start_send` accepts an item now. Otherwise the current task is notified when
+    /// it may accept one.
+    pub fn poll_ready(&mut self) -> Poll<(), Rc<Error>> {
+        if self.buffered_bytes > self.high_water_mark {
+            try_ready!(self.poll_complete());
         }
 
-        use self::PublishState::*;
-        self.state = match &mut self.state {
-            &mut Processing(ref mut _published) => unreachable!(),
-            &mut Waiting(ref mut sink) => {
-                let sink = sink.take();
-                let published = publish_(sink, self.channel, bytes, self.option.clone());
-                Processing(published)
-            }
+        self.channel.check_open()?;
+
+        if self.channel.conn.is_blocked() {
+            self.channel.conn.notify_on_unblocked();
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(()))
+    }
+
+
+    /// Buffer frames of `message` regardless of the high-water mark. Check `poll_ready` first.
+    pub(crate) fn push_message(
+        &mut self,
+        message: Message,
+        option: &PublishOption,
+    ) -> Result<(), Rc<Error>> {
+        let size = estimated_size(&message, option);
+        let frames = publish_frames(&self.channel, message, option);
+        self.buffered_bytes += size + frames.len() * FRAME_OVERHEAD;
+        self.buffer.extend(frames);
+
+        if self.guard.is_none() {
+            self.guard = Some(PublishGuard::new(&self.channel.conn));
+        }
+
+        // Frames which `sink` does not accept now are given in `poll_complete`.
+        self.write_buffered()?;
+        Ok(())
+    }
+
+
+    /// Give buffered frames to `sink` without flushing it.
+    fn write_buffered(&mut self) -> Poll<(), Rc<Error>> {
+        let sink = self.sink.as_mut().expect("PublishSink is used after drop");
+        write_frames(sink, &mut self.buffer)
+    }
+}
+
+
+impl<Out: Outgo, T: PublishItem> Drop for PublishSink<Out, T> {
+    fn drop(&mut self) {
+        // Every accepted item is flushed already.
+        let guard = match self.guard.take() {
+            Some(guard) => guard,
+            None => return,
         };
+        if self.channel.conn.is_closed() {
+            return;
+        }
+
+        // `sink` may have accepted a part of a message. If the rest did not follow it, server
+        // would close the connection by 505 UNEXPECTED_FRAME.
+        let flush = Flush {
+            sink: self.sink.take().expect("PublishSink is dropped twice"),
+            buffer: mem::replace(&mut self.buffer, VecDeque::new()),
+            _channel: self.channel.clone(),
+            _guard: guard,
+        };
+        let flush = flush.map_err(|e| warn!("Fail to flush a dropped PublishSink : {}", e));
+        self.channel.conn.handle.spawn(flush);
+    }
+}
+
+
+
+/// Write and flush frames left by a dropped `PublishSink`.
+struct Flush<Out: Outgo> {
+    sink: Out,
+    buffer: VecDeque<Frame>,
+
+    // Channel is closed when its last handle is dropped. Keep it until frames are flushed, as
+    // well as the guard which `GlobalChannel::close` waits for.
+    _channel: Rc<ChannelHandle>,
+    _guard: PublishGuard,
+}
 
+
+impl<Out: Outgo> Future for Flush<Out> {
+    type Item = ();
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<(), Rc<Error>> {
+        try_ready!(write_frames(&mut self.sink, &mut self.buffer));
+        self.sink.poll_complete()
+    }
+}
+
+
+fn write_frames<Out: Outgo>(sink: &mut Out, buffer: &mut VecDeque<Frame>) -> Poll<(), Rc<Error>> {
+    while let Some(frame) = buffer.pop_front() {
+        if let AsyncSink::NotReady(frame) = sink.start_send(frame)? {
+            buffer.push_front(frame);
+            return Ok(Async::NotReady);
+        }
+    }
+    Ok(Async::Ready(()))
+}
+
+
+/// Rough size of frames of a message without frame overheads.
+fn estimated_size(message: &Message, option: &PublishOption) -> usize {
+    FIXED_FIELDS_SIZE + option.exchange.len() + option.routing_key.len() +
+        message.properties.estimated_size() + message.body.len()
+}
+
+
+impl<Out: Outgo, T: PublishItem> Sink for PublishSink<Out, T> {
+    type SinkItem = T;
+    type SinkError = Rc<Error>;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, Self::SinkError> {
+        if let Async::NotReady = self.poll_ready()? {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let (message, option) = item.into_publish(&self.option);
+        self.push_message(message, &option)?;
         Ok(AsyncSink::Ready)
     }
 
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
-        use self::PublishState::*;
-
-        self.state = match &mut self.state {
-            &mut Processing(ref mut processing) => {
-                let sink = try_ready!(processing.poll());
-                Waiting(Should::new(sink))
-            }
-            &mut Waiting(_) => return Ok(Async::Ready(())),
-        };
+        try_ready!(self.write_buffered());
+        try_ready!(self.sink.as_mut().expect("PublishSink is used after drop").poll_complete());
+        self.buffered_bytes = 0;
+        self.guard = None;
+        Ok(Async::Ready(()))
+    }
+}
+
+
+
+/// Send `Basic.Publish`, a content header and content bodies.
+fn publish_<Out: Outgo>(
+    sink: Out,
+    channel: &ChannelHandle,
+    message: Message,
+    option: &PublishOption,
+) -> Published<Out> {
+    let frames = publish_frames(channel, message, option);
+    let fut = sink.send_all(stream::iter_ok::<_, Rc<Error>>(frames)).map(
+        |(sink, _)| sink,
+    );
+    Box::new(fut)
+}
+
+
+/// Body is split so that each frame fits in `frame_max` negotiated with server.
+pub(crate) fn publish_frames(
+    channel: &ChannelHandle,
+    message: Message,
+    option: &PublishOption,
+) -> Vec<Frame> {
+    let bytes = message.body;
+    let publish = MethodPayload::Basic(ClassMethod::Publish(PublishMethod {
+        reserved1: 0,
+        exchange: option.exchange.clone(),
+        routing_key: option.routing_key.clone(),
+        mandatory: option.is_mandatory,
+        immediate: option.is_immediate,
+    }));
+    let header = ContentHeaderPayload {
+        class_id: BASIC_CLASS_ID,
+        body_size: bytes.len() as u64,
+        properties: message.properties.into_properties(),
+    };
+
+    let mut frames = vec![
+        method_frame(channel.id, publish),
+        content_frame(channel.id, FramePayload::ContentHeader(header)),
+    ];
+
+    for chunk in split_body(bytes, channel.conn.max_body_size()) {
+        let body = ContentBodyPayload { bytes: chunk };
+        frames.push(content_frame(channel.id, FramePayload::ContentBody(body)));
+    }
+
+    frames
+}
+
+
+/// Split `bytes` into payloads of content body frames. Empty body has no frame.
+fn split_body(mut bytes: Bytes, max_body_size: Option<usize>) -> Vec<Bytes> {
+    let max_body_size = cmp::max(max_body_size.unwrap_or(bytes.len()), 1);
+    let mut chunks = Vec::new();
+    while !bytes.is_empty() {
+        let len = cmp::min(max_body_size, bytes.len());
+        chunks.push(bytes.split_to(len));
+    }
+    chunks
+}
+
+
+fn content_frame(channel_id: u16, payload: FramePayload) -> Frame {
+    Frame {
+        header: FrameHeader { channel: channel_id },
+        payload: payload,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_body_by_max_body_size() {
+        let body = Bytes::from(&b"0123456789"[..]);
+        let chunks = split_body(body, Some(4));
+        assert_eq!(chunks, vec![Bytes::from("0123"), Bytes::from("4567"), Bytes::from("89")]);
+    }
+
+
+    #[test]
+    fn body_which_fits_is_not_split() {
+        let body = Bytes::from("0123");
+        assert_eq!(split_body(body.clone(), Some(4)), vec![body.clone()]);
+        assert_eq!(split_body(body.clone(), None), vec![body]);
+    }
+
+
+    #[test]
+    fn empty_body_has_no_frame() {
+        assert!(split_body(Bytes::new(), Some(4)).is_empty());
+        assert!(split_body(Bytes::new(), None).is_empty());
+    }
+
 
-        self.poll_complete()
+    #[test]
+    fn zero_max_body_size_still_progresses() {
+        let chunks = split_body(Bytes::from("ab"), Some(0));
+        assert_eq!(chunks, vec![Bytes::from("a"), Bytes::from("b")]);
     }
 }
@@ -0,0 +1,56 @@
+use futures::Future;
+
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{ClassMethod, QosMethod};
+
+use unsync::method::call_method;
+use super::{Income, Outgo, LocalChannel, LocalChannelFuture, Recorded};
+
+
+/// Option of `Basic.Qos` method.
+#[derive(Clone, PartialEq, Debug)]
+pub struct QosOption {
+    /// Window size of prefetched message in octets. 0 means no limit.
+    pub prefetch_size: u32,
+
+    /// Number of prefetched message. 0 means no limit.
+    pub prefetch_count: u16,
+
+    /// Apply to the entire connection rather than this channel.
+    pub is_global: bool,
+}
+
+
+
+pub fn qos<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: QosOption,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Basic(ClassMethod::Qos(QosMethod {
+        prefetch_size: option.prefetch_size,
+        prefetch_count: option.prefetch_count,
+        global: option.is_global,
+    }));
+
+    let qos_ok = call_method(ch.income, ch.outgo, ch_id, method, |frame| {
+        frame.method().and_then(|c| c.basic()).and_then(
+            |m| m.qos_ok(),
+        ).map(|_| ())
+    });
+
+    let fut = qos_ok.map(move |((), income, outgo)| {
+        if let Some(ref recorder) = recorder {
+            recorder.record(Recorded::Qos(option));
+        }
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
@@ -0,0 +1,367 @@
+use futures::Future;
+use futures::future;
+
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::queue::{ClassMethod, DeclareMethod, BindMethod, DeleteMethod,
+                                 PurgeMethod, UnbindMethod};
+
+use std::rc::Rc;
+use std::io;
+
+use unsync::method::{call_method, call_method_no_wait};
+use super::{Income, Outgo, LocalChannel, LocalChannelFuture, DeclareQueueFuture, Recorded,
+            Arguments};
+use errors::*;
+
+
+/// Future returning the number of messages reported by server and `LocalChannel`.
+/// The number is `None` if the operation is `no_wait`.
+pub type MessageCountFuture<In, Out> = Box<
+    Future<
+        Item = (Option<u32>, LocalChannel<In, Out>),
+        Error = Rc<Error>,
+    >,
+>;
+
+
+/// Future returning `QueueInfo` and `LocalChannel`.
+pub type QueueInfoFuture<In, Out> = Box<
+    Future<
+        Item = (QueueInfo, LocalChannel<In, Out>),
+        Error = Rc<Error>,
+    >,
+>;
+
+
+/// Result of `Queue.Declare` reported by server.
+/// Counts are `None` if the queue is declared with `no_wait`, because server does not reply.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QueueInfo {
+    pub name: String,
+
+    /// Number of messages which are ready to be delivered.
+    pub message_count: Option<u32>,
+
+    /// Number of active consumers.
+    pub consumer_count: Option<u32>,
+}
+
+
+impl From<QueueInfo> for String {
+    fn from(info: QueueInfo) -> String {
+        info.name
+    }
+}
+
+
+impl<'a> From<&'a QueueInfo> for String {
+    fn from(info: &'a QueueInfo) -> String {
+        info.name.clone()
+    }
+}
+
+
+/// Option of `Queue.Declare`.
+///
+/// Since 0.4.0, this is amqpr's own type with `arguments`, instead of the re-export of
+/// `amqpr_api::queue::declare::DeclareQueueOption`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DeclareQueueOption {
+    /// Empty name lets server generate a name.
+    pub name: String,
+    pub is_passive: bool,
+    pub is_durable: bool,
+    pub is_exclusive: bool,
+    pub is_auto_delete: bool,
+    pub is_no_wait: bool,
+    pub arguments: Arguments,
+}
+
+
+/// Option of `Queue.Bind`.
+///
+/// Since 0.4.0, this is amqpr's own type with `arguments`, instead of the re-export of
+/// `amqpr_api::queue::bind::BindQueueOption`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BindQueueOption {
+    pub queue: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub is_no_wait: bool,
+    pub arguments: Arguments,
+}
+
+
+/// Option of `Queue.Delete`.
+#[derive(Clone, Debug)]
+pub struct DeleteQueueOption {
+    pub queue: String,
+
+    /// Delete only if the queue has no consumers.
+    pub if_unused: bool,
+
+    /// Delete only if the queue has no messages.
+    pub if_empty: bool,
+
+    pub is_no_wait: bool,
+}
+
+
+/// Option of `Queue.Purge`.
+#[derive(Clone, Debug)]
+pub struct PurgeQueueOption {
+    pub queue: String,
+    pub is_no_wait: bool,
+}
+
+
+/// Option of `Queue.Unbind`. `Queue.Unbind` does not have `no_wait` flag.
+#[derive(Clone, Debug)]
+pub struct UnbindQueueOption {
+    pub queue: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub arguments: Arguments,
+}
+
+
+
+pub fn declare_queue<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: DeclareQueueOption,
+) -> DeclareQueueFuture<In, Out> {
+    let fut = declare_queue_info(ch, option).map(|(info, ch)| (info.name, ch));
+    Box::new(fut)
+}
+
+
+pub fn declare_queue_info<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: DeclareQueueOption,
+) -> QueueInfoFuture<In, Out> {
+    if option.is_no_wait && option.name.is_empty() {
+        let msg = "Name of a queue declared with no_wait must not be empty because server does \
+                   not tell the generated name";
+        let e = io::Error::new(io::ErrorKind::InvalidInput, msg);
+        return Box::new(future::err(Rc::new(Error::from(e))));
+    }
+
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let record = match option.is_passive {
+        false => Some(option.clone()),
+        true => None,
+    };
+    let requested_name = option.name.clone();
+    let method = MethodPayload::Queue(ClassMethod::Declare(DeclareMethod {
+        reserved1: 0,
+        queue: option.name,
+        passive: option.is_passive,
+        durable: option.is_durable,
+        exclusive: option.is_exclusive,
+        auto_delete: option.is_auto_delete,
+        no_wait: option.is_no_wait,
+        arguments: option.arguments.into_table(),
+    }));
+
+    let declared = call_method_no_wait(
+        ch.income,
+        ch.outgo,
+        ch_id,
+        method,
+        option.is_no_wait,
+        |frame| {
+            frame.method().and_then(|c| c.queue()).and_then(|m| m.declare_ok()).map(
+                |m| {
+                    QueueInfo {
+                        name: m.queue.clone(),
+                        message_count: Some(m.message_count),
+                        consumer_count: Some(m.consumer_count),
+                    }
+                },
+            )
+        },
+    );
+
+    let fut = declared.map(move |(info, income, outgo)| {
+        // Server does not reply on no_wait. We only know the name we requested.
+        let info = info.unwrap_or_else(|| {
+            QueueInfo {
+                name: requested_name,
+                message_count: None,
+                consumer_count: None,
+            }
+        });
+        if let (Some(recorder), Some(option)) = (recorder.as_ref(), record) {
+            recorder.record(Recorded::Queue(option, info.name.clone()));
+        }
+        let ch = LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        };
+        (info, ch)
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn bind_queue<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: BindQueueOption,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let record = Recorded::QueueBinding(option.clone());
+    let method = MethodPayload::Queue(ClassMethod::Bind(BindMethod {
+        reserved1: 0,
+        queue: option.queue,
+        exchange: option.exchange,
+        routing_key: option.routing_key,
+        no_wait: option.is_no_wait,
+        arguments: option.arguments.into_table(),
+    }));
+
+    let no_wait = option.is_no_wait;
+    let bound = call_method_no_wait(ch.income, ch.outgo, ch_id, method, no_wait, |frame| {
+        frame.method().and_then(|c| c.queue()).and_then(
+            |m| m.bind_ok(),
+        ).map(|_| ())
+    });
+
+    let fut = bound.map(move |(_, income, outgo)| {
+        if let Some(ref recorder) = recorder {
+            recorder.record(record);
+        }
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn delete_queue<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: DeleteQueueOption,
+) -> MessageCountFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Queue(ClassMethod::Delete(DeleteMethod {
+        reserved1: 0,
+        queue: option.queue.clone(),
+        if_unused: option.if_unused,
+        if_empty: option.if_empty,
+        no_wait: option.is_no_wait,
+    }));
+
+    let deleted = call_method_no_wait(
+        ch.income,
+        ch.outgo,
+        ch_id,
+        method,
+        option.is_no_wait,
+        |frame| {
+            frame.method().and_then(|c| c.queue()).and_then(|m| m.delete_ok()).map(
+                |m| m.message_count,
+            )
+        },
+    );
+
+    let fut = deleted.map(move |(count, income, outgo)| {
+        if let Some(ref recorder) = recorder {
+            recorder.remove_queue(&option.queue);
+        }
+        let ch = LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        };
+        (count, ch)
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn purge_queue<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: PurgeQueueOption,
+) -> MessageCountFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Queue(ClassMethod::Purge(PurgeMethod {
+        reserved1: 0,
+        queue: option.queue,
+        no_wait: option.is_no_wait,
+    }));
+
+    let purged = call_method_no_wait(
+        ch.income,
+        ch.outgo,
+        ch_id,
+        method,
+        option.is_no_wait,
+        |frame| {
+            frame.method().and_then(|c| c.queue()).and_then(|m| m.purge_ok()).map(
+                |m| m.message_count,
+            )
+        },
+    );
+
+    let fut = purged.map(move |(count, income, outgo)| {
+        let ch = LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        };
+        (count, ch)
+    });
+
+    Box::new(fut)
+}
+
+
+pub fn unbind_queue<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+    option: UnbindQueueOption,
+) -> LocalChannelFuture<In, Out> {
+    let (ch_id, recorder, channel) = (ch.channel_id, ch.recorder, ch.channel);
+    let method = MethodPayload::Queue(ClassMethod::Unbind(UnbindMethod {
+        reserved1: 0,
+        queue: option.queue.clone(),
+        exchange: option.exchange.clone(),
+        routing_key: option.routing_key.clone(),
+        arguments: option.arguments.into_table(),
+    }));
+
+    let unbound = call_method(ch.income, ch.outgo, ch_id, method, |frame| {
+        frame.method().and_then(|c| c.queue()).and_then(
+            |m| m.unbind_ok(),
+        ).map(|_| ())
+    });
+
+    let (queue, exchange, routing_key) = (option.queue, option.exchange, option.routing_key);
+    let fut = unbound.map(move |((), income, outgo)| {
+        if let Some(ref recorder) = recorder {
+            recorder.remove_queue_binding(&queue, &exchange, &routing_key);
+        }
+        LocalChannel {
+            channel_id: ch_id,
+            income: income,
+            outgo: outgo,
+            recorder: recorder,
+            channel: channel,
+        }
+    });
+
+    Box::new(fut)
+}
@@ -0,0 +1,264 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{QosOption, DeclareExchangeOption, DeclareQueueOption, BindQueueOption,
+            BindExchangeOption, SubscribeOption};
+
+
+/// A topology operation performed through `LocalChannel`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Recorded {
+    Exchange(DeclareExchangeOption),
+
+    /// Declared option and the name which was given by server at the first declaration.
+    Queue(DeclareQueueOption, String),
+
+    QueueBinding(BindQueueOption),
+
+    ExchangeBinding(BindExchangeOption),
+
+    Qos(QosOption),
+
+    /// Consumer started by `subscribe_stream`. It is not replayed because nobody receives its
+    /// items. `RecoveringSubscribeStream` restarts its own consumer with the recorded option.
+    Consumer(SubscribeOption),
+}
+
+
+impl Recorded {
+    /// Returns true if both records are about the same exchange, queue, binding, QoS or consumer.
+    fn is_same(&self, other: &Recorded) -> bool {
+        use self::Recorded::*;
+        match (self, other) {
+            (&Exchange(ref a), &Exchange(ref b)) => a.name == b.name,
+            (&Queue(_, ref a), &Queue(_, ref b)) => a == b,
+            (&QueueBinding(ref a), &QueueBinding(ref b)) => a == b,
+            (&ExchangeBinding(ref a), &ExchangeBinding(ref b)) => a == b,
+            (&Qos(ref a), &Qos(ref b)) => a.is_global == b.is_global,
+            (&Consumer(ref a), &Consumer(ref b)) => a.consumer_tag == b.consumer_tag,
+            _ => false,
+        }
+    }
+}
+
+
+/// Recorder of topology declared through `LocalChannel`.
+/// Recorded operations are replayed when connection is recovered.
+/// See `recovery` module.
+#[derive(Clone)]
+pub struct Recorder {
+    records: Rc<RefCell<Vec<Recorded>>>,
+
+    // Server named queue may get another name when it is re-declared.
+    // This maps the name at the first declaration to the current name.
+    queue_names: Rc<RefCell<HashMap<String, String>>>,
+}
+
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder {
+            records: Rc::new(RefCell::new(Vec::new())),
+            queue_names: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+
+    /// Returns all recorded operations in the order they were performed.
+    pub fn records(&self) -> Vec<Recorded> {
+        self.records.borrow().clone()
+    }
+
+
+    /// Returns the current name of a queue which was named `name` when it is recorded.
+    pub fn queue_name(&self, name: &str) -> String {
+        self.queue_names
+            .borrow()
+            .get(name)
+            .cloned()
+            .unwrap_or(name.into())
+    }
+
+
+    /// Returns the recorded option of a consumer.
+    pub(crate) fn consumer(&self, consumer_tag: &str) -> Option<SubscribeOption> {
+        self.records.borrow().iter().filter_map(|record| match record {
+            &Recorded::Consumer(ref option) if option.consumer_tag == consumer_tag => {
+                Some(option.clone())
+            }
+            _ => None,
+        }).next()
+    }
+
+
+    /// Record an operation unless the same one is already recorded.
+    /// The first record is kept so that queue names are the ones at the first declaration,
+    /// except QoS whose last setting is in effect.
+    pub(crate) fn record(&self, record: Recorded) {
+        let mut records = self.records.borrow_mut();
+        match records.iter().position(|recorded| recorded.is_same(&record)) {
+            Some(i) => {
+                if let Recorded::Qos(_) = record {
+                    records[i] = record;
+                }
+            }
+            None => {
+                if let Recorded::Queue(_, ref name) = record {
+                    self.queue_names.borrow_mut().insert(
+                        name.clone(),
+                        name.clone(),
+                    );
+                }
+                records.push(record);
+            }
+        }
+    }
+
+
+    /// Forget a deleted exchange and bindings from / to it.
+    pub(crate) fn remove_exchange(&self, exchange: &str) {
+        self.records.borrow_mut().retain(|record| match record {
+            &Recorded::Exchange(ref option) => option.name != exchange,
+            &Recorded::QueueBinding(ref option) => option.exchange != exchange,
+            &Recorded::ExchangeBinding(ref option) => {
+                option.source != exchange && option.destination != exchange
+            }
+            _ => true,
+        });
+    }
+
+
+    pub(crate) fn remove_exchange_binding(&self, unbound: &BindExchangeOption) {
+        self.records.borrow_mut().retain(|record| match record {
+            &Recorded::ExchangeBinding(ref option) => {
+                option.destination != unbound.destination || option.source != unbound.source ||
+                    option.routing_key != unbound.routing_key
+            }
+            _ => true,
+        });
+    }
+
+
+    /// Forget a deleted queue and bindings to it. `queue` is the current name.
+    pub(crate) fn remove_queue(&self, queue: &str) {
+        let is_target = |name: &str| self.queue_name(name) == queue;
+        self.records.borrow_mut().retain(|record| match record {
+            &Recorded::Queue(_, ref name) => !is_target(name),
+            &Recorded::QueueBinding(ref option) => !is_target(&option.queue),
+            &Recorded::Consumer(ref option) => !is_target(&option.queue),
+            _ => true,
+        });
+    }
+
+
+    pub(crate) fn remove_queue_binding(&self, queue: &str, exchange: &str, routing_key: &str) {
+        let is_target = |option: &BindQueueOption| {
+            self.queue_name(&option.queue) == queue && option.exchange == exchange &&
+                option.routing_key == routing_key
+        };
+        self.records.borrow_mut().retain(|record| match record {
+            &Recorded::QueueBinding(ref option) => !is_target(option),
+            _ => true,
+        });
+    }
+
+
+    pub(crate) fn rename_queue(&self, recorded_name: &str, current_name: String) {
+        self.queue_names.borrow_mut().insert(
+            recorded_name.into(),
+            current_name,
+        );
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Arguments;
+
+    fn queue(name: &str) -> Recorded {
+        let option = DeclareQueueOption {
+            name: name.into(),
+            is_passive: false,
+            is_durable: false,
+            is_exclusive: false,
+            is_auto_delete: false,
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        };
+        Recorded::Queue(option, name.into())
+    }
+
+
+    fn binding(queue: &str) -> Recorded {
+        Recorded::QueueBinding(BindQueueOption {
+            queue: queue.into(),
+            exchange: "logs".into(),
+            routing_key: "".into(),
+            is_no_wait: false,
+            arguments: Arguments::new(),
+        })
+    }
+
+
+    fn qos(prefetch_count: u16) -> Recorded {
+        Recorded::Qos(QosOption {
+            prefetch_size: 0,
+            prefetch_count: prefetch_count,
+            is_global: false,
+        })
+    }
+
+
+    fn consumer(queue: &str) -> Recorded {
+        Recorded::Consumer(SubscribeOption {
+            queue: queue.into(),
+            consumer_tag: "consumer".into(),
+            is_no_local: false,
+            is_exclusive: true,
+            arguments: Arguments::new(),
+        })
+    }
+
+
+    #[test]
+    fn record_same_operation_once() {
+        let recorder = Recorder::new();
+        recorder.record(queue("q"));
+        recorder.record(binding("q"));
+        recorder.record(queue("q"));
+        recorder.record(binding("q"));
+        assert_eq!(recorder.records(), vec![queue("q"), binding("q")]);
+    }
+
+
+    #[test]
+    fn keep_first_queue_name_of_consumer() {
+        let recorder = Recorder::new();
+        recorder.record(queue("amq.gen-a"));
+        recorder.record(consumer("amq.gen-a"));
+        recorder.rename_queue("amq.gen-a", "amq.gen-b".into());
+
+        // Re-subscribed after recovery.
+        recorder.record(consumer("amq.gen-b"));
+        assert_eq!(recorder.consumer("consumer").unwrap().queue, "amq.gen-a");
+        assert_eq!(recorder.queue_name("amq.gen-a"), "amq.gen-b");
+
+        recorder.remove_queue("amq.gen-b");
+        assert!(recorder.consumer("consumer").is_none());
+        assert!(recorder.records().is_empty());
+    }
+
+
+    #[test]
+    fn replace_qos() {
+        let recorder = Recorder::new();
+        recorder.record(qos(10));
+        recorder.record(queue("q"));
+        recorder.record(qos(20));
+        assert_eq!(recorder.records(), vec![qos(20), queue("q")]);
+    }
+}
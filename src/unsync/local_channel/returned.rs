@@ -0,0 +1,351 @@
+use futures::{Stream, Poll, Async};
+
+use ex_futures::stream::StreamExt;
+
+use amqpr_codec::Frame;
+
+use bytes::BytesMut;
+
+use std::rc::Rc;
+
+use unsync::{Income, Outgo, BoxedIncome};
+use super::{LocalChannel, ChannelHandle, Message, BasicProperties};
+use errors::*;
+
+
+/// A message returned by server by `Basic.Return`, because it is published with `mandatory` and
+/// no queue is bound for it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReturnedMessage {
+    /// Reply code such as 312 (NO_ROUTE).
+    pub reply_code: u16,
+    pub reply_text: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub message: Message,
+}
+
+
+
+pub fn returned_stream<In: Income, Out: Outgo>(
+    ch: LocalChannel<In, Out>,
+) -> (LocalChannel<BoxedIncome, Out>, ReturnedStream) {
+    let mut checker = ReturnChecker::new();
+    let (returned_income, others_income) = ch.income.unsync_fork(move |f| checker.check(f));
+
+    let stream = ReturnedStream {
+        income: Box::new(returned_income),
+        channel: ch.channel.clone(),
+        assembler: ReturnAssembler::new(),
+    };
+
+    let local_ch = LocalChannel {
+        channel_id: ch.channel_id,
+        income: Box::new(others_income) as BoxedIncome,
+        outgo: ch.outgo,
+        recorder: ch.recorder,
+        channel: ch.channel,
+    };
+
+    (local_ch, stream)
+}
+
+
+
+/// Stream of `ReturnedMessage`.
+/// Like `SubscribeStream`, this stream ends when the channel or the connection is closed, and
+/// fails if server closes either of them.
+pub struct ReturnedStream {
+    income: BoxedIncome,
+    channel: Rc<ChannelHandle>,
+    assembler: ReturnAssembler,
+}
+
+
+impl Stream for ReturnedStream {
+    type Item = ReturnedMessage;
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<Option<ReturnedMessage>, Rc<Error>> {
+        loop {
+            if let Some(e) = self.channel.error() {
+                return Err(e);
+            }
+            if self.channel.conn.is_closed() || self.channel.is_closed() {
+                return Ok(Async::Ready(None));
+            }
+
+            let frame = match self.income.poll()? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => {
+                    self.channel.conn.notify_on_close();
+                    self.channel.notify_on_close();
+                    return Ok(Async::NotReady);
+                }
+            };
+
+            if let Some(returned) = self.assembler.push(&frame) {
+                return Ok(Async::Ready(Some(returned)));
+            }
+        }
+    }
+}
+
+
+
+/// Predicate of `unsync_fork` which accepts `Basic.Return` and its content frames.
+pub(crate) struct ReturnChecker {
+    expect: Expect,
+}
+
+enum Expect {
+    Return,
+    ContentHeader,
+    ContentBody { remaining: u64 },
+}
+
+
+impl ReturnChecker {
+    pub(crate) fn new() -> ReturnChecker {
+        ReturnChecker { expect: Expect::Return }
+    }
+
+
+    pub(crate) fn check(&mut self, frame: &Frame) -> bool {
+        let new_expect = match self.expect {
+            Expect::Return => {
+                match is_return(frame) {
+                    true => Expect::ContentHeader,
+                    false => return false,
+                }
+            }
+            Expect::ContentHeader => {
+                match frame.content_header() {
+                    Some(header) if header.body_size == 0 => Expect::Return,
+                    Some(header) => Expect::ContentBody { remaining: header.body_size },
+                    None => return false,
+                }
+            }
+            Expect::ContentBody { remaining } => {
+                match frame.content_body() {
+                    Some(body) => {
+                        match remaining.checked_sub(body.bytes.len() as u64) {
+                            Some(0) | None => Expect::Return,
+                            Some(remaining) => Expect::ContentBody { remaining: remaining },
+                        }
+                    }
+                    None => return false,
+                }
+            }
+        };
+
+        self.expect = new_expect;
+
+        true
+    }
+}
+
+
+fn is_return(frame: &Frame) -> bool {
+    frame
+        .method()
+        .and_then(|c| c.basic())
+        .and_then(|m| m.return_())
+        .is_some()
+}
+
+
+
+/// Build `ReturnedMessage` from frames accepted by `ReturnChecker`.
+pub(crate) struct ReturnAssembler {
+    partial: Option<Partial>,
+}
+
+struct Partial {
+    returned: ReturnedMessage,
+    body: BytesMut,
+    remaining: u64,
+}
+
+
+impl ReturnAssembler {
+    pub(crate) fn new() -> ReturnAssembler {
+        ReturnAssembler { partial: None }
+    }
+
+
+    /// Returns `ReturnedMessage` when its last frame arrives.
+    pub(crate) fn push(&mut self, frame: &Frame) -> Option<ReturnedMessage> {
+        if let Some(m) = frame.method().and_then(|c| c.basic()).and_then(|m| m.return_()) {
+            let returned = ReturnedMessage {
+                reply_code: m.reply_code,
+                reply_text: m.reply_text.clone(),
+                exchange: m.exchange.clone(),
+                routing_key: m.routing_key.clone(),
+                message: Message::default(),
+            };
+            self.partial = Some(Partial {
+                returned: returned,
+                body: BytesMut::new(),
+                remaining: 0,
+            });
+            return None;
+        }
+
+        if let Some(header) = frame.content_header() {
+            if let Some(ref mut partial) = self.partial {
+                partial.returned.message.properties =
+                    BasicProperties::from_properties(&header.properties);
+                partial.remaining = header.body_size;
+                partial.body.reserve(header.body_size as usize);
+            }
+        } else if let Some(body) = frame.content_body() {
+            if let Some(ref mut partial) = self.partial {
+                partial.body.extend_from_slice(&body.bytes);
+                partial.remaining = partial.remaining.saturating_sub(body.bytes.len() as u64);
+            }
+        } else {
+            warn!("Unexpected frame is discarded : {:?}", frame);
+            return None;
+        }
+
+        match self.partial.as_ref().map(|partial| partial.remaining) {
+            Some(0) => {
+                self.partial.take().map(|mut partial| {
+                    partial.returned.message.body = partial.body.freeze();
+                    partial.returned
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amqpr_codec::{FrameHeader, FramePayload};
+    use amqpr_codec::method::MethodPayload;
+    use amqpr_codec::method::basic::{ClassMethod, ReturnMethod, AckMethod};
+    use amqpr_codec::content_header::{ContentHeaderPayload, Properties};
+    use amqpr_codec::content_body::ContentBodyPayload;
+    use bytes::Bytes;
+    use unsync::method::method_frame;
+
+    fn return_frame() -> Frame {
+        let return_ = ReturnMethod {
+            reply_code: 312,
+            reply_text: "NO_ROUTE".into(),
+            exchange: "logs".into(),
+            routing_key: "app.error".into(),
+        };
+        method_frame(1, MethodPayload::Basic(ClassMethod::Return(return_)))
+    }
+
+
+    fn ack_frame() -> Frame {
+        let ack = AckMethod {
+            delivery_tag: 1,
+            multiple: false,
+        };
+        method_frame(1, MethodPayload::Basic(ClassMethod::Ack(ack)))
+    }
+
+
+    fn header_frame(body_size: u64, message_id: Option<&str>) -> Frame {
+        let mut properties = Properties::new();
+        properties.message_id = message_id.map(String::from);
+        let header = ContentHeaderPayload {
+            class_id: 60,
+            body_size: body_size,
+            properties: properties,
+        };
+        Frame {
+            header: FrameHeader { channel: 1 },
+            payload: FramePayload::ContentHeader(header),
+        }
+    }
+
+
+    fn body_frame(bytes: &'static str) -> Frame {
+        Frame {
+            header: FrameHeader { channel: 1 },
+            payload: FramePayload::ContentBody(ContentBodyPayload { bytes: Bytes::from(bytes) }),
+        }
+    }
+
+
+    #[test]
+    fn check_return_and_its_content() {
+        let mut checker = ReturnChecker::new();
+        assert!(!checker.check(&ack_frame()));
+        assert!(checker.check(&return_frame()));
+        assert!(checker.check(&header_frame(5, None)));
+        assert!(checker.check(&body_frame("abc")));
+        assert!(checker.check(&body_frame("de")));
+
+        // Content of a delivery is not accepted after the return is complete.
+        assert!(!checker.check(&header_frame(2, None)));
+        assert!(!checker.check(&body_frame("xy")));
+        assert!(!checker.check(&ack_frame()));
+    }
+
+
+    #[test]
+    fn check_return_without_body() {
+        let mut checker = ReturnChecker::new();
+        assert!(checker.check(&return_frame()));
+        assert!(checker.check(&header_frame(0, None)));
+        assert!(!checker.check(&body_frame("xy")));
+        assert!(checker.check(&return_frame()));
+    }
+
+
+    #[test]
+    fn assemble_body_split_into_frames() {
+        let mut assembler = ReturnAssembler::new();
+        assert_eq!(assembler.push(&return_frame()), None);
+        assert_eq!(assembler.push(&header_frame(5, Some("order-42"))), None);
+        assert_eq!(assembler.push(&body_frame("abc")), None);
+
+        let returned = assembler.push(&body_frame("de")).unwrap();
+        assert_eq!(returned.reply_code, 312);
+        assert_eq!(returned.reply_text, "NO_ROUTE");
+        assert_eq!(returned.exchange, "logs");
+        assert_eq!(returned.routing_key, "app.error");
+        assert_eq!(returned.message.body, Bytes::from("abcde"));
+        assert_eq!(returned.message.properties.message_id, Some("order-42".into()));
+    }
+
+
+    #[test]
+    fn assemble_empty_body() {
+        let mut assembler = ReturnAssembler::new();
+        assert_eq!(assembler.push(&return_frame()), None);
+        let returned = assembler.push(&header_frame(0, None)).unwrap();
+        assert!(returned.message.body.is_empty());
+    }
+
+
+    #[test]
+    fn assemble_returns_one_by_one() {
+        let mut assembler = ReturnAssembler::new();
+        let frames = vec![
+            return_frame(),
+            header_frame(1, None),
+            body_frame("a"),
+            return_frame(),
+            header_frame(2, None),
+            body_frame("bc"),
+        ];
+        let bodies: Vec<_> = frames
+            .iter()
+            .filter_map(|frame| assembler.push(frame))
+            .map(|returned| returned.message.body)
+            .collect();
+        assert_eq!(bodies, vec![Bytes::from("a"), Bytes::from("bc")]);
+    }
+}
@@ -3,19 +3,35 @@ use futures::{Future, Stream, Poll, Async};
 use ex_futures::sink::{SinkExt, UnsyncCloneable};
 use ex_futures::stream::StreamExt;
 
-use amqpr_api::basic::consume::{start_consume_wait, ConsumeStarted};
 use amqpr_api::basic::deliver::{receive_delivered, Delivered};
 use amqpr_codec::Frame;
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::basic::{ClassMethod, ConsumeMethod};
 
 use bytes::Bytes;
 
 use std::rc::Rc;
 
 use unsync::{Income, Outgo, BoxedIncome, LocalChannel};
+use unsync::method::{call_method, MethodFuture};
+use super::{ChannelHandle, Arguments, Recorded};
 use errors::Error;
 
 
-pub use amqpr_api::basic::consume::StartConsumeOption as SubscribeOption;
+/// Option of `Basic.Consume`.
+/// `SubscribeStream` always consumes with `no_ack` and waits for `Basic.Consume-Ok`, so there is
+/// no field for them.
+///
+/// Since 0.4.0, this is amqpr's own type instead of the re-export of
+/// `amqpr_api::basic::consume::StartConsumeOption`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SubscribeOption {
+    pub queue: String,
+    pub consumer_tag: String,
+    pub is_no_local: bool,
+    pub is_exclusive: bool,
+    pub arguments: Arguments,
+}
 
 
 
@@ -26,22 +42,32 @@ pub use amqpr_api::basic::consume::StartConsumeOption as SubscribeOption;
 /// function.
 pub fn subscribe_stream<In: Income, Out: Outgo>(
     local_ch: LocalChannel<In, Out>,
-    queue: String,
-    consumer_tag: String,
-    no_local: bool,
-    exclusive: bool,
+    option: SubscribeOption,
 ) -> (LocalChannel<BoxedIncome, UnsyncCloneable<Out>>,
       SubscribeStream<BoxedIncome, UnsyncCloneable<Out>>) {
-    let option = SubscribeOption {
-        queue: queue,
-        consumer_tag: consumer_tag.clone(),
-        is_no_local: no_local,
-        is_no_ack: true,
-        is_exclusive: exclusive,
-        is_no_wait: false,
-    };
+    if let Some(ref recorder) = local_ch.recorder {
+        recorder.record(Recorded::Consumer(option.clone()));
+    }
 
-    let (id, income, outgo) = (local_ch.channel_id, local_ch.income, local_ch.outgo);
+    let consumer_tag = option.consumer_tag.clone();
+    let consume = MethodPayload::Basic(ClassMethod::Consume(ConsumeMethod {
+        reserved1: 0,
+        queue: option.queue,
+        consumer_tag: option.consumer_tag,
+        no_local: option.is_no_local,
+        no_ack: true,
+        exclusive: option.is_exclusive,
+        no_wait: false,
+        arguments: option.arguments.into_table(),
+    }));
+
+    let (id, income, outgo, recorder, channel) = (
+        local_ch.channel_id,
+        local_ch.income,
+        local_ch.outgo,
+        local_ch.recorder,
+        local_ch.channel,
+    );
     let cloneable_outgo = outgo.unsync_cloneable();
 
     // Create `SubscribeStream`
@@ -50,19 +76,29 @@ pub fn subscribe_stream<In: Income, Out: Outgo>(
         expect: Expect::ConsumeOk,
     };
     let (subscribe_income, others_income) = income.unsync_fork(move |frame| checker.check(frame));
-    let consume_started = start_consume_wait(
+    let consume_started = call_method(
         Box::new(subscribe_income) as BoxedIncome,
         cloneable_outgo.clone(),
         id,
-        option,
+        consume,
+        |frame| {
+            frame.method().and_then(|c| c.basic()).and_then(
+                |m| m.consume_ok(),
+            ).map(|_| ())
+        },
     );
-    let sub_stream = SubscribeStream::SendingConsumeMethod(consume_started);
+    let sub_stream = SubscribeStream {
+        state: SubscribeState::SendingConsumeMethod(consume_started),
+        channel: channel.clone(),
+    };
 
     // Create LocalChannel
     let local_ch = LocalChannel {
         channel_id: id,
         income: Box::new(others_income) as BoxedIncome,
         outgo: cloneable_outgo,
+        recorder: recorder,
+        channel: channel,
     };
 
     (local_ch, sub_stream)
@@ -142,8 +178,18 @@ impl ItemChecker {
 /// But that cause a decreasing of reliability.
 /// If you want reliability rather than performance, you should use `subscribe_stream_ack`
 /// function.
-pub enum SubscribeStream<In: Income, Out: Outgo> {
-    SendingConsumeMethod(ConsumeStarted<In, Out>),
+///
+/// This stream ends when the connection is closed by `GlobalChannel::close` or the channel is
+/// closed by `LocalChannel::close`. If server closes either of them, this stream fails with
+/// `ClosedByServer` error.
+pub struct SubscribeStream<In: Income, Out: Outgo> {
+    state: SubscribeState<In, Out>,
+    channel: Rc<ChannelHandle>,
+}
+
+
+enum SubscribeState<In: Income, Out: Outgo> {
+    SendingConsumeMethod(MethodFuture<(), In, Out>),
     ReceivingDeliverd(Delivered<In>),
 }
 
@@ -153,11 +199,37 @@ impl<In: Income, Out: Outgo> Stream for SubscribeStream<In, Out> {
     type Error = Rc<Error>;
 
     fn poll(&mut self) -> Poll<Option<Bytes>, Self::Error> {
-        use self::SubscribeStream::*;
+        if let Some(e) = self.channel.error() {
+            return Err(e);
+        }
+        if self.channel.conn.is_closed() || self.channel.is_closed() {
+            return Ok(Async::Ready(None));
+        }
+
+        match self.poll_delivered() {
+            Ok(Async::NotReady) => {
+                self.channel.conn.notify_on_close();
+                self.channel.notify_on_close();
+                Ok(Async::NotReady)
+            }
+            // Socket may be shut down while closing.
+            Err(ref e) if self.channel.conn.is_closing() && self.channel.error().is_none() => {
+                debug!("Error while closing connection is ignored : {:?}", e);
+                Ok(Async::Ready(None))
+            }
+            res => res,
+        }
+    }
+}
+
+
+impl<In: Income, Out: Outgo> SubscribeStream<In, Out> {
+    fn poll_delivered(&mut self) -> Poll<Option<Bytes>, Rc<Error>> {
+        use self::SubscribeState::*;
 
-        let (bytes_opt, income) = match self {
+        let (bytes_opt, income) = match &mut self.state {
             &mut SendingConsumeMethod(ref mut fut) => {
-                let (income, outgo) = try_ready!(fut.poll());
+                let ((), income, outgo) = try_ready!(fut.poll());
                 drop(outgo);
                 (None, income)
             }
@@ -168,11 +240,11 @@ impl<In: Income, Out: Outgo> Stream for SubscribeStream<In, Out> {
         };
 
         let del = receive_delivered(income);
-        *self = ReceivingDeliverd(del);
+        self.state = ReceivingDeliverd(del);
 
         match bytes_opt {
             Some(bytes) => Ok(Async::Ready(Some(bytes))),
-            None => self.poll(),
+            None => self.poll_delivered(),
         }
     }
 }
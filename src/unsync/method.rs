@@ -0,0 +1,125 @@
+//! Helpers to send a method frame which `amqpr_api` does not provide yet.
+
+use futures::{Future, Stream, Sink};
+use futures::future::{self, Loop};
+
+use amqpr_codec::{Frame, FrameHeader, FramePayload};
+use amqpr_codec::method::MethodPayload;
+
+use std::rc::Rc;
+use std::io;
+
+use super::{Income, Outgo};
+use errors::*;
+
+
+pub(crate) type MethodFuture<T, In, Out> = Box<Future<Item = (T, In, Out), Error = Rc<Error>>>;
+
+
+pub(crate) fn method_frame(channel_id: u16, method: MethodPayload) -> Frame {
+    Frame {
+        header: FrameHeader { channel: channel_id },
+        payload: FramePayload::Method(method),
+    }
+}
+
+
+/// Send a method frame without waiting any response.
+pub(crate) fn send_method<Out: Outgo>(
+    outgo: Out,
+    channel_id: u16,
+    method: MethodPayload,
+) -> Box<Future<Item = Out, Error = Rc<Error>>> {
+    Box::new(outgo.send(method_frame(channel_id, method)))
+}
+
+
+/// Send a method frame and wait for a response frame.
+/// `extract` returns `Some` if a given frame is the expected response.
+/// Other frames arriving before the response are discarded.
+pub(crate) fn call_method<In, Out, F, T>(
+    income: In,
+    outgo: Out,
+    channel_id: u16,
+    method: MethodPayload,
+    extract: F,
+) -> MethodFuture<T, In, Out>
+where
+    In: Income,
+    Out: Outgo,
+    F: Fn(&Frame) -> Option<T> + 'static,
+    T: 'static,
+{
+    let fut = send_method(outgo, channel_id, method).and_then(move |outgo| {
+        wait_response(income, extract).map(move |(res, income)| (res, income, outgo))
+    });
+    Box::new(fut)
+}
+
+
+/// Same with `call_method` but does not wait for the response if `no_wait` is true.
+/// Returned item is `None` in that case.
+pub(crate) fn call_method_no_wait<In, Out, F, T>(
+    income: In,
+    outgo: Out,
+    channel_id: u16,
+    method: MethodPayload,
+    no_wait: bool,
+    extract: F,
+) -> MethodFuture<Option<T>, In, Out>
+where
+    In: Income,
+    Out: Outgo,
+    F: Fn(&Frame) -> Option<T> + 'static,
+    T: 'static,
+{
+    match no_wait {
+        true => {
+            let fut = send_method(outgo, channel_id, method).map(
+                move |outgo| (None, income, outgo),
+            );
+            Box::new(fut)
+        }
+        false => {
+            let fut = call_method(income, outgo, channel_id, method, extract).map(
+                |(res, income, outgo)| (Some(res), income, outgo),
+            );
+            Box::new(fut)
+        }
+    }
+}
+
+
+/// Wait for a frame accepted by `extract`.
+pub(crate) fn wait_response<In, F, T>(
+    income: In,
+    extract: F,
+) -> Box<Future<Item = (T, In), Error = Rc<Error>>>
+where
+    In: Income,
+    F: Fn(&Frame) -> Option<T> + 'static,
+    T: 'static,
+{
+    let extract = Rc::new(extract);
+    let fut = future::loop_fn(income, move |income| {
+        let extract = extract.clone();
+        income.into_future().map_err(|(e, _)| e).and_then(
+            move |(frame, income)| match frame {
+                Some(frame) => {
+                    match extract(&frame) {
+                        Some(res) => Ok(Loop::Break((res, income))),
+                        None => {
+                            warn!("Unexpected frame is discarded : {:?}", frame);
+                            Ok(Loop::Continue(income))
+                        }
+                    }
+                }
+                None => Err(Rc::new(Error::from(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection is closed before response arrives",
+                )))),
+            },
+        )
+    });
+    Box::new(fut)
+}
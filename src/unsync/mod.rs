@@ -1,8 +1,33 @@
 mod global_channel;
 mod local_channel;
-
-pub use self::global_channel::{GlobalChannel, connect};
-pub use self::local_channel::{LocalChannel, PublishSink};
+mod tls;
+mod handshake;
+mod watchdog;
+mod connection;
+mod close_watcher;
+mod blocked;
+mod reader;
+pub(crate) mod method;
+pub mod sasl;
+
+pub use self::global_channel::{GlobalChannel, OpenedChannels, DEFAULT_HEARTBEAT_SEC,
+                               CLOSE_TIMEOUT_SEC, connect, connect_uri, connect_amqp_uri,
+                               connect_amqp_uri_with_tls, connect_amqp_uri_with_option};
+pub use self::handshake::{HandshakeOption, Tuning, FRAME_MIN_SIZE};
+pub use self::sasl::{Sasl, SaslMechanism};
+pub use self::watchdog::Watchdog;
+pub use self::blocked::BlockedEvent;
+pub use self::tls::{TlsOption, Certificate, Identity, connect_tls};
+pub use self::local_channel::{LocalChannel, Message, RoutedMessage, PublishItem, BasicProperties,
+                              DeliveryMode, PublishSink, PublishOption, DEFAULT_HIGH_WATER_MARK,
+                              ConfirmPublishSink, ConfirmSinkFuture, Confirmation, ReturnedStream,
+                              ReturnedMessage, SubscribeStream, SubscribeOption,
+                              QosOption, Arguments,
+                              HeadersMatch, Overflow, QueueType, ExchangeType,
+                              DeclareExchangeOption, BindExchangeOption, DeclareQueueOption,
+                              BindQueueOption, DeleteQueueOption, PurgeQueueOption,
+                              UnbindQueueOption, MessageCountFuture, QueueInfoFuture, QueueInfo,
+                              Recorder, Recorded};
 
 
 use futures::{Stream, Sink, Future};
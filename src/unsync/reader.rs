@@ -0,0 +1,171 @@
+//! Background task which owns the read side of a connection.
+//! It keeps reading even if nobody polls `GlobalChannel` or `LocalChannel`, so that a dead peer,
+//! `Connection.Close`, `Channel.Close` and `Connection.Blocked` are noticed by publish-only users
+//! too.
+
+use futures::{Future, Stream, Poll, Async};
+use futures::stream;
+use futures::unsync::mpsc::{self, UnboundedSender};
+use futures::unsync::oneshot::Sender;
+
+use amqpr_codec::{Frame, FramePayload};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::{connection, channel};
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::io;
+use std::mem;
+
+use super::{Income, BoxedIncome};
+use super::connection::Connection;
+use super::blocked::blocked_event;
+use super::close_watcher::closed_by_server;
+use super::method::method_frame;
+use super::watchdog::Watchdog;
+use errors::*;
+
+
+/// Sender of `HeartbeatTimeout` (or an error of sending heartbeat) shared by the reader and the
+/// heartbeat sender. Whichever fails first takes it.
+pub(crate) type HeartbeatNotify = Rc<RefCell<Option<Sender<Rc<Error>>>>>;
+
+
+/// Spawn a reader of `income` and returns a stream of frames read by it.
+/// Frames are buffered until the returned stream is polled.
+pub(crate) fn spawn_reader<In: Income>(income: In, conn: &Rc<Connection>) -> BoxedIncome {
+    let (tx, rx) = mpsc::unbounded();
+    let reader = Reader {
+        income: Box::new(income),
+        conn: Rc::downgrade(conn),
+        tx: tx,
+        notify: None,
+    };
+    conn.handle.spawn(reader);
+
+    let income = rx.then(|res| match res {
+        Ok(res) => res,
+        Err(()) => unreachable!("UnboundedReceiver never fails"),
+    });
+    Box::new(income)
+}
+
+
+struct Reader {
+    income: BoxedIncome,
+
+    // Reader stops when every handle of the connection is dropped.
+    conn: Weak<Connection>,
+
+    tx: UnboundedSender<Result<Frame, Rc<Error>>>,
+
+    // Set when heartbeat watching starts.
+    notify: Option<HeartbeatNotify>,
+}
+
+
+impl Reader {
+    /// Returns an error which closes the connection.
+    fn dispatch(&mut self, conn: &Connection, frame: Frame) -> Result<(), Rc<Error>> {
+        if let FramePayload::Heartbeat = frame.payload {
+            debug!("Heartbeat is received");
+            return Ok(());
+        }
+
+        let closed = frame
+            .method()
+            .and_then(|c| c.connection())
+            .and_then(|m| m.close())
+            .map(|m| closed_by_server(0, m.reply_code, &m.reply_text, m.class_id, m.method_id));
+        if let Some(e) = closed {
+            error!("{}", e);
+            let close_ok = MethodPayload::Connection(connection::ClassMethod::CloseOk);
+            conn.send_frame(method_frame(0, close_ok));
+            return Err(Rc::new(Error::from(e)));
+        }
+
+        if let Some(event) = blocked_event(&frame) {
+            conn.set_blocked(event);
+            return Ok(());
+        }
+
+        let channel_id = frame.header.channel;
+        let channel_closed = frame
+            .method()
+            .and_then(|c| c.channel())
+            .and_then(|m| m.close())
+            .map(|m| {
+                closed_by_server(channel_id, m.reply_code, &m.reply_text, m.class_id, m.method_id)
+            });
+        if let Some(e) = channel_closed {
+            error!("{}", e);
+            let close_ok = MethodPayload::Channel(channel::ClassMethod::CloseOk);
+            conn.send_frame(method_frame(channel_id, close_ok));
+            conn.on_channel_closed_by_server(channel_id, Rc::new(Error::from(e)));
+        }
+
+        let close_ok = frame.method().and_then(|c| c.channel()).and_then(|m| m.close_ok());
+        if close_ok.is_some() {
+            conn.on_channel_close_ok(channel_id);
+        }
+
+        // Nobody may read frames of this connection (e.g. publish-only). Just ignore them.
+        let _ = self.tx.unbounded_send(Ok(frame));
+        Ok(())
+    }
+
+
+    fn fail(&mut self, conn: &Connection, e: Rc<Error>) {
+        if find::<HeartbeatTimeout>(&e).is_some() {
+            let tx = self.notify.take().and_then(|notify| notify.borrow_mut().take());
+            if let Some(tx) = tx {
+                let _ = tx.send(e.clone());
+            }
+        }
+        if !conn.is_closed() {
+            conn.set_failed(e.clone());
+        }
+        let _ = self.tx.unbounded_send(Err(e));
+    }
+}
+
+
+impl Future for Reader {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let conn = match self.conn.upgrade() {
+            Some(conn) => conn,
+            None => {
+                debug!("Connection is dropped. Stop reading");
+                return Ok(Async::Ready(()));
+            }
+        };
+
+        conn.register_reader();
+        if let Some((interval, notify)) = conn.take_heartbeat_watch() {
+            let income = mem::replace(&mut self.income, Box::new(stream::empty()));
+            self.income = Box::new(Watchdog::new(income, interval, &conn.handle));
+            self.notify = Some(notify);
+        }
+
+        loop {
+            let res = match self.income.poll() {
+                Ok(Async::Ready(Some(frame))) => self.dispatch(&conn, frame),
+                Ok(Async::Ready(None)) if conn.is_closing() => return Ok(Async::Ready(())),
+                Ok(Async::Ready(None)) => {
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "Socket is closed");
+                    Err(Rc::new(Error::from(e)))
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = res {
+                self.fail(&conn, e);
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! SASL mechanisms to authenticate a connection by `Connection.StartOk` and
+//! `Connection.Secure`.
+
+use std::fmt;
+use std::rc::Rc;
+use std::io;
+
+use errors::*;
+
+
+/// SASL mechanism with challenge / response rounds.
+/// A new instance is created on each handshake so that it can hold the state of rounds.
+///
+/// Responses and challenges are binary in AMQP, but `amqpr_codec` carries them as `String`.
+/// So a response must be valid UTF-8, otherwise handshake fails with `InvalidData` error, and
+/// only UTF-8 challenges reach `respond`.
+pub trait SaslMechanism {
+    /// Name of this mechanism such as "PLAIN". It must be offered by server.
+    fn name(&self) -> String;
+
+    /// Response sent with `Connection.StartOk`.
+    fn initial_response(&mut self) -> Result<Vec<u8>, Rc<Error>>;
+
+    /// Response to a challenge of `Connection.Secure`.
+    /// Default implementation fails because most mechanisms do not expect any challenge.
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, Rc<Error>> {
+        let msg = format!(
+            "{} mechanism does not expect challenge : {:?}",
+            self.name(),
+            challenge
+        );
+        Err(Rc::new(Error::from(io::Error::new(io::ErrorKind::InvalidData, msg))))
+    }
+}
+
+
+
+/// SASL mechanism used by handshake.
+#[derive(Clone)]
+pub enum Sasl {
+    /// PLAIN mechanism with `user` and `pass` of `HandshakeOption`.
+    Plain,
+
+    /// AMQPLAIN mechanism (RabbitMQ extension) with `user` and `pass` of `HandshakeOption`.
+    AmqPlain,
+
+    /// EXTERNAL mechanism. Server authenticates client by TLS client certificate, so you should
+    /// connect with `TlsOption` which has `identity`.
+    External,
+
+    /// User supplied mechanism. Given function is called on each handshake.
+    Custom(Rc<Fn() -> Box<SaslMechanism>>),
+}
+
+
+impl Sasl {
+    pub fn custom<F>(f: F) -> Sasl
+    where
+        F: Fn() -> Box<SaslMechanism> + 'static,
+    {
+        Sasl::Custom(Rc::new(f))
+    }
+
+
+    pub(crate) fn mechanism(&self, user: &str, pass: &str) -> Box<SaslMechanism> {
+        match self {
+            &Sasl::Plain => Box::new(Plain::new(user, pass)),
+            &Sasl::AmqPlain => Box::new(AmqPlain::new(user, pass)),
+            &Sasl::External => Box::new(External),
+            &Sasl::Custom(ref f) => f(),
+        }
+    }
+}
+
+
+impl Default for Sasl {
+    fn default() -> Sasl {
+        Sasl::Plain
+    }
+}
+
+
+impl fmt::Debug for Sasl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Sasl::Plain => write!(f, "Plain"),
+            &Sasl::AmqPlain => write!(f, "AmqPlain"),
+            &Sasl::External => write!(f, "External"),
+            &Sasl::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+
+
+/// PLAIN mechanism defined by RFC 4616.
+#[derive(Clone, Debug)]
+pub struct Plain {
+    pub user: String,
+    pub pass: String,
+}
+
+
+impl Plain {
+    pub fn new<S: Into<String>, T: Into<String>>(user: S, pass: T) -> Plain {
+        Plain {
+            user: user.into(),
+            pass: pass.into(),
+        }
+    }
+}
+
+
+impl SaslMechanism for Plain {
+    fn name(&self) -> String {
+        "PLAIN".into()
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Rc<Error>> {
+        Ok(format!("\u{0}{}\u{0}{}", self.user, self.pass).into_bytes())
+    }
+}
+
+
+
+/// AMQPLAIN mechanism. Response is a field table (without its length) which has "LOGIN" and
+/// "PASSWORD".
+/// Because its length prefixes are binary, user and password must be shorter than 128 bytes.
+#[derive(Clone, Debug)]
+pub struct AmqPlain {
+    pub user: String,
+    pub pass: String,
+}
+
+
+impl AmqPlain {
+    pub fn new<S: Into<String>, T: Into<String>>(user: S, pass: T) -> AmqPlain {
+        AmqPlain {
+            user: user.into(),
+            pass: pass.into(),
+        }
+    }
+}
+
+
+impl SaslMechanism for AmqPlain {
+    fn name(&self) -> String {
+        "AMQPLAIN".into()
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Rc<Error>> {
+        let mut bytes = Vec::new();
+        encode_long_string_field(&mut bytes, "LOGIN", &self.user);
+        encode_long_string_field(&mut bytes, "PASSWORD", &self.pass);
+        Ok(bytes)
+    }
+}
+
+
+fn encode_long_string_field(bytes: &mut Vec<u8>, key: &str, value: &str) {
+    bytes.push(key.len() as u8);
+    bytes.extend_from_slice(key.as_bytes());
+    bytes.push(b'S');
+    let len = value.len() as u32;
+    bytes.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+
+
+/// EXTERNAL mechanism. Authentication is done outside of AMQP such as TLS client certificate.
+#[derive(Clone, Debug)]
+pub struct External;
+
+
+impl SaslMechanism for External {
+    fn name(&self) -> String {
+        "EXTERNAL".into()
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Rc<Error>> {
+        Ok(Vec::new())
+    }
+}
+
+
+
+/// `amqpr_codec` represents long string fields such as `response` of `Connection.StartOk` by
+/// `String`, though they are binary in AMQP. Fails if `response` of `mechanism` is not UTF-8.
+pub(crate) fn into_long_string(mechanism: &str, response: Vec<u8>) -> Result<String, Rc<Error>> {
+    String::from_utf8(response).map_err(|e| {
+        let msg = format!(
+            "Response of {} mechanism is not UTF-8, which can not be sent : {:?}",
+            mechanism,
+            e.as_bytes()
+        );
+        Rc::new(Error::from(io::Error::new(io::ErrorKind::InvalidData, msg)))
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_response() {
+        let response = Plain::new("guest", "secret").initial_response().unwrap();
+        assert_eq!(response, b"\x00guest\x00secret".to_vec());
+    }
+
+
+    #[test]
+    fn amqplain_response() {
+        let response = AmqPlain::new("guest", "secret").initial_response().unwrap();
+        let expected = b"\x05LOGINS\x00\x00\x00\x05guest\x08PASSWORDS\x00\x00\x00\x06secret";
+        assert_eq!(response, expected.to_vec());
+    }
+
+
+    #[test]
+    fn amqplain_response_with_long_password() {
+        let pass = "p".repeat(200);
+        let response = AmqPlain::new("guest", pass.as_str()).initial_response().unwrap();
+
+        let mut expected = b"\x05LOGINS\x00\x00\x00\x05guest\x08PASSWORDS\x00\x00\x00\xc8".to_vec();
+        expected.extend_from_slice(pass.as_bytes());
+        assert_eq!(response, expected);
+    }
+
+
+    #[test]
+    fn utf8_response_is_long_string() {
+        let response = Plain::new("guest", "secret").initial_response().unwrap();
+        let string = into_long_string("PLAIN", response).unwrap();
+        assert_eq!(string, "\u{0}guest\u{0}secret");
+    }
+
+
+    #[test]
+    fn reject_non_utf8_response() {
+        let pass = "p".repeat(200);
+        let response = AmqPlain::new("guest", pass.as_str()).initial_response().unwrap();
+        assert!(into_long_string("AMQPLAIN", response).is_err());
+    }
+
+
+    #[test]
+    fn external_response_is_empty() {
+        assert!(External.initial_response().unwrap().is_empty());
+    }
+
+
+    #[test]
+    fn reject_challenge_by_default() {
+        assert!(Plain::new("guest", "guest").respond(b"challenge").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use tokio_core::reactor::Handle;
+use tokio_core::net::TcpStream;
+use futures::Future;
+use futures::future;
+
+use native_tls;
+use tokio_tls::{self, TlsStream};
+
+use amqpr_api::handshake::Handshaker;
+
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::io;
+
+use super::{BoxedIncome, BoxedOutgo, AmqpFuture};
+use super::global_channel::{GlobalChannel, handshake};
+use errors::*;
+
+pub use native_tls::{Certificate, Identity};
+
+
+/// Option of TLS transport.
+pub struct TlsOption {
+    /// Domain name used for SNI and for verifying server certificate.
+    pub domain: String,
+
+    /// Additional trusted root certificates (e.g. self-signed CA of your broker).
+    pub root_certificates: Vec<Certificate>,
+
+    /// Client certificate and private key sent to server if it requests.
+    pub identity: Option<Identity>,
+
+    /// Whether sending `domain` as SNI or not.
+    pub use_sni: bool,
+
+    /// If `false`, server certificate is accepted even if it is issued for other host name.
+    /// Certificate chain is still verified.
+    pub verify_hostname: bool,
+}
+
+
+impl TlsOption {
+    /// Create default option which verifies server certificate against `domain` using
+    /// system trusted root certificates.
+    pub fn new<S: Into<String>>(domain: S) -> TlsOption {
+        TlsOption {
+            domain: domain.into(),
+            root_certificates: Vec::new(),
+            identity: None,
+            use_sni: true,
+            verify_hostname: true,
+        }
+    }
+
+    fn connector(&self) -> Result<native_tls::TlsConnector, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for cert in self.root_certificates.iter() {
+            builder.add_root_certificate(cert.clone());
+        }
+        if let Some(ref identity) = self.identity {
+            builder.identity(identity.clone());
+        }
+        builder.use_sni(self.use_sni);
+        builder.danger_accept_invalid_hostnames(!self.verify_hostname);
+        builder.build()
+    }
+}
+
+
+
+/// Connect to AMQP server over TLS.
+/// Returned `GlobalChannel` is same with one returned by `connect`.
+pub fn connect_tls<H: Handshaker + 'static>(
+    addr: &SocketAddr,
+    handshaker: H,
+    option: &TlsOption,
+    handle: &Handle,
+) -> Box<AmqpFuture<GlobalChannel<BoxedIncome, BoxedOutgo>>> {
+    let tcp = TcpStream::connect(addr, handle);
+    let handle = handle.clone();
+    start_tls(tcp, option, move |socket| handshake(socket, handshaker, &handle))
+}
+
+
+/// Establish TLS session on `tcp` and then start AMQP handshake by `start_handshake`.
+pub(crate) fn start_tls<F, H>(
+    tcp: F,
+    option: &TlsOption,
+    start_handshake: H,
+) -> Box<AmqpFuture<GlobalChannel<BoxedIncome, BoxedOutgo>>>
+where
+    F: Future<Item = TcpStream, Error = io::Error> + 'static,
+    H: FnOnce(TlsStream<TcpStream>) -> Box<AmqpFuture<GlobalChannel<BoxedIncome, BoxedOutgo>>>
+        + 'static,
+{
+    let connector = match option.connector() {
+        Ok(connector) => tokio_tls::TlsConnector::from(connector),
+        Err(e) => return Box::new(future::err(tls_error(e))),
+    };
+    let domain = option.domain.clone();
+
+    let fut = tcp.map_err(|e| Rc::new(Error::from(e)))
+        .and_then(move |socket| {
+            connector.connect(domain.as_str(), socket).map_err(tls_error)
+        })
+        .and_then(|socket| {
+            debug!("TLS session is established");
+            start_handshake(socket)
+        });
+
+    Box::new(fut)
+}
+
+
+fn tls_error(e: native_tls::Error) -> Rc<Error> {
+    Rc::new(Error::from(io::Error::new(io::ErrorKind::Other, e)))
+}
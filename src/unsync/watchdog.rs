@@ -0,0 +1,89 @@
+use tokio_core::reactor::{Handle, Timeout};
+use futures::{Stream, Future, Poll, Async};
+
+use amqpr_codec::{Frame, FramePayload};
+
+use std::time::{Duration, Instant};
+use std::rc::Rc;
+
+use super::Income;
+use errors::*;
+
+
+/// Stream of inbound frames which fails with `HeartbeatTimeout` when no frame arrives within
+/// two heartbeat intervals.
+/// Heartbeat frames are consumed by this stream.
+pub struct Watchdog<In: Income> {
+    income: In,
+    interval: Duration,
+    timer: Option<Timeout>,
+    handle: Handle,
+}
+
+
+impl<In: Income> Watchdog<In> {
+    pub fn new(income: In, interval: Duration, handle: &Handle) -> Watchdog<In> {
+        Watchdog {
+            income: income,
+            interval: interval,
+            timer: None,
+            handle: handle.clone(),
+        }
+    }
+
+
+    fn reset(&mut self) -> Result<(), Rc<Error>> {
+        let deadline = Instant::now() + self.interval * 2;
+        match self.timer {
+            Some(ref mut timer) => timer.reset(deadline),
+            None => {
+                let timer = Timeout::new_at(deadline, &self.handle).map_err(|e| {
+                    Rc::new(Error::from(e))
+                })?;
+                self.timer = Some(timer);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl<In: Income> Stream for Watchdog<In> {
+    type Item = Frame;
+    type Error = Rc<Error>;
+
+    fn poll(&mut self) -> Poll<Option<Frame>, Rc<Error>> {
+        if self.timer.is_none() {
+            self.reset()?;
+        }
+
+        loop {
+            match self.income.poll()? {
+                Async::Ready(Some(frame)) => {
+                    self.reset()?;
+                    if let FramePayload::Heartbeat = frame.payload {
+                        debug!("Heartbeat is received");
+                        continue;
+                    }
+                    return Ok(Async::Ready(Some(frame)));
+                }
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => break,
+            }
+        }
+
+        let fired = match self.timer {
+            Some(ref mut timer) => timer.poll().map_err(|e| Rc::new(Error::from(e)))?,
+            None => unreachable!(),
+        };
+
+        match fired {
+            Async::Ready(()) => {
+                error!("Server does not respond within {:?}", self.interval * 2);
+                let e = HeartbeatTimeout { interval: self.interval };
+                Err(Rc::new(Error::from(e)))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
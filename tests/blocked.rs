@@ -0,0 +1,48 @@
+//! `Connection.Blocked` pushed by the mock broker in `tests/support`.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod support;
+
+use futures::Stream;
+use tokio_core::reactor::Core;
+
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::connection::{ClassMethod, BlockedMethod};
+
+use amqpr::unsync::{connect_uri, BlockedEvent};
+
+use support::MockTuning;
+
+
+#[test]
+fn notice_blocked_without_polling_income() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, _received, push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (global, local) = core.run(global.open_new_channel()).unwrap();
+    let (_local, _sink) = local.publish_sink("", "blocked");
+    let events = global.blocked_events();
+    assert!(!global.is_blocked());
+
+    let blocked = ClassMethod::Blocked(BlockedMethod { reason: "low on memory".into() });
+    push.unbounded_send(support::method_frame(0, MethodPayload::Connection(blocked)))
+        .unwrap();
+    let (event, events) = core.run(events.into_future()).map_err(|_| ()).unwrap();
+    assert_eq!(event, Some(BlockedEvent::Blocked { reason: "low on memory".into() }));
+    assert!(global.is_blocked());
+
+    let unblocked = ClassMethod::Unblocked;
+    push.unbounded_send(support::method_frame(0, MethodPayload::Connection(unblocked)))
+        .unwrap();
+    let (event, _) = core.run(events.into_future()).map_err(|_| ()).unwrap();
+    assert_eq!(event, Some(BlockedEvent::Unblocked));
+    assert!(!global.is_blocked());
+}
@@ -0,0 +1,64 @@
+//! Channel id allocation against the mock broker in `tests/support`.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod support;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::{Core, Timeout};
+
+use amqpr::unsync::connect_uri;
+use amqpr::errors::{self, ChannelIdError};
+
+use std::time::Duration;
+
+use support::MockTuning;
+
+
+#[test]
+fn keep_id_of_dropped_channel_until_close_ok() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, _received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (global, local) = core.run(global.open_channel(1)).unwrap();
+    drop(local);
+
+    let e = match core.run(global.open_channel(1)) {
+        Ok(_) => panic!("Id is reused before Channel.Close-Ok"),
+        Err(e) => e,
+    };
+    match errors::find::<ChannelIdError>(&e) {
+        Some(&ChannelIdError::InUse(1)) => (),
+        other => panic!("Unexpected error : {:?}", other),
+    }
+}
+
+
+#[test]
+fn reuse_id_of_dropped_channel_after_close_ok() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (global, local) = core.run(global.open_channel(1)).unwrap();
+    drop(local);
+
+    // Wait until the broker receives Channel.Close and its Close-Ok comes back.
+    let close = received
+        .filter(|f| f.method().and_then(|c| c.channel()).and_then(|m| m.close()).is_some())
+        .into_future()
+        .map_err(|_| ());
+    core.run(close).unwrap();
+    core.run(Timeout::new(Duration::from_millis(100), &handle).unwrap()).unwrap();
+
+    core.run(global.open_channel(1)).unwrap();
+}
@@ -0,0 +1,46 @@
+//! Graceful `GlobalChannel::close` against the mock broker in `tests/support`.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod support;
+
+use futures::Future;
+use futures::future::Either;
+use tokio_core::reactor::{Core, Timeout};
+
+use bytes::Bytes;
+
+use amqpr::unsync::connect_uri;
+
+use std::time::Duration;
+
+use support::MockTuning;
+
+
+#[test]
+fn close_waits_for_publish_which_is_not_polled_yet() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, _received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (global, local) = core.run(global.open_new_channel()).unwrap();
+
+    let publishing = local.publish(Bytes::from("hello"), "", "close");
+    let closing = global.close(200, "bye");
+
+    let timeout = Timeout::new(Duration::from_millis(200), &handle).unwrap();
+    let closing = match core.run(closing.select2(timeout)) {
+        Ok(Either::B((_, closing))) => closing,
+        Ok(Either::A(_)) => panic!("Connection is closed before the publish is done"),
+        Err(_) => panic!("Fail to close"),
+    };
+
+    core.run(publishing.join(closing)).unwrap();
+}
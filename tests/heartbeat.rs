@@ -0,0 +1,61 @@
+//! Heartbeat watching against the mock broker in `tests/support`, which never sends heartbeat.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod support;
+
+use futures::{Future, Sink};
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use amqpr::unsync::connect_uri;
+use amqpr::errors::{self, HeartbeatTimeout};
+
+use support::MockTuning;
+
+
+#[test]
+fn detect_dead_peer_without_polling_income() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let tuning = MockTuning {
+        heartbeat: 1,
+        ..MockTuning::default()
+    };
+    let (addr, _received, _push) = support::spawn_broker(tuning, &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (global, timeout) = global.start_heartbeat(&handle);
+    let (_global, local) = core.run(global.open_new_channel()).unwrap();
+
+    // Only publish. Nobody polls inbound frames.
+    let (_local, sink) = local.publish_sink("", "heartbeat");
+    let sink = core.run(sink.send(Bytes::from("before timeout"))).unwrap();
+
+    let e = core.run(timeout).unwrap();
+    assert!(errors::find::<HeartbeatTimeout>(&e).is_some());
+    assert!(core.run(sink.send(Bytes::from("after timeout"))).is_err());
+}
+
+
+#[test]
+fn client_can_disable_heartbeat() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let tuning = MockTuning {
+        heartbeat: 60,
+        ..MockTuning::default()
+    };
+    let (addr, _received, _push) = support::spawn_broker(tuning, &handle);
+
+    let uri = format!("amqp://guest:guest@{}?heartbeat=0", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    assert_eq!(global.tuning().unwrap().heartbeat, 0);
+}
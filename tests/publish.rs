@@ -0,0 +1,58 @@
+//! `PublishSink` against the mock broker in `tests/support`.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate bytes;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod support;
+
+use futures::{Sink, Stream, AsyncSink};
+use futures::future;
+use tokio_core::reactor::Core;
+
+use bytes::Bytes;
+
+use amqpr::unsync::connect_uri;
+
+use support::MockTuning;
+
+
+#[test]
+fn flush_whole_message_of_dropped_sink() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (_global, local) = core.run(global.open_new_channel()).unwrap();
+    let (_local, mut sink) = local.publish_sink("", "dropped");
+
+    // Larger than `frame_max` of the mock broker, so it is split into 3 body frames.
+    let size = 300 * 1024;
+    let sent = future::lazy(move || {
+        let res = sink.start_send(Bytes::from(vec![0u8; size]));
+        drop(sink);
+        res
+    });
+    match core.run(sent).unwrap() {
+        AsyncSink::Ready => (),
+        AsyncSink::NotReady(_) => panic!("Sink does not accept the first item"),
+    }
+
+    let frames = received
+        .skip_while(|f| Ok(!support::is_publish(f)))
+        .take(5)
+        .collect();
+    let frames = core.run(frames).unwrap();
+    assert!(frames[1].content_header().is_some());
+    let body_size: usize = frames
+        .iter()
+        .filter_map(|f| f.content_body())
+        .map(|body| body.bytes.len())
+        .sum();
+    assert_eq!(body_size, size);
+}
@@ -0,0 +1,77 @@
+//! `Queue.Declare` with `no_wait` against the mock broker in `tests/support`, which never
+//! replies to it.
+
+extern crate amqpr;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod support;
+
+use tokio_core::reactor::Core;
+
+use amqpr::unsync::{connect_uri, DeclareQueueOption, Arguments, Recorder, Recorded};
+
+use std::io;
+
+use support::MockTuning;
+
+
+fn no_wait_option(name: &str) -> DeclareQueueOption {
+    DeclareQueueOption {
+        name: name.into(),
+        is_passive: false,
+        is_durable: true,
+        is_exclusive: false,
+        is_auto_delete: false,
+        is_no_wait: true,
+        arguments: Arguments::new(),
+    }
+}
+
+
+#[test]
+fn declare_with_no_wait_has_no_counts() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, _received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (_global, local) = core.run(global.open_new_channel()).unwrap();
+
+    let declared = local.declare_queue_info_with_option(no_wait_option("jobs"));
+    let (info, _local) = core.run(declared).unwrap();
+    assert_eq!(info.name, "jobs");
+    assert_eq!(info.message_count, None);
+    assert_eq!(info.consumer_count, None);
+}
+
+
+#[test]
+fn reject_server_named_queue_with_no_wait() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (addr, _received, _push) = support::spawn_broker(MockTuning::default(), &handle);
+
+    let uri = format!("amqp://guest:guest@{}", addr);
+    let global = core.run(connect_uri(&uri, &handle)).unwrap();
+    let (_global, local) = core.run(global.open_new_channel()).unwrap();
+    let recorder = Recorder::new();
+    let local = local.record(recorder.clone());
+
+    let e = match core.run(local.declare_queue_with_option(no_wait_option(""))) {
+        Ok(_) => panic!("Server named queue is declared with no_wait"),
+        Err(e) => e,
+    };
+    match e.kind() {
+        &amqpr::errors::ErrorKind::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+        other => panic!("Unexpected error : {:?}", other),
+    }
+    let queues = recorder.records().into_iter().filter(|r| match r {
+        &Recorded::Queue(..) => true,
+        _ => false,
+    });
+    assert_eq!(queues.count(), 0);
+}
@@ -0,0 +1,169 @@
+//! Minimal AMQP broker on localhost for tests. It does handshake, opens and closes channels,
+//! and reports every frame it receives. It does not route anything.
+
+#![allow(dead_code)]
+
+use futures::{Future, Stream, Sink};
+use futures::unsync::mpsc::{self, UnboundedSender, UnboundedReceiver};
+use tokio_core::reactor::Handle;
+use tokio_core::net::TcpListener;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::read_exact;
+
+use amqpr_codec::{Frame, FrameHeader, FramePayload, Codec};
+use amqpr_codec::method::MethodPayload;
+use amqpr_codec::method::connection::{self, StartMethod, TuneMethod};
+use amqpr_codec::method::channel;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::io;
+
+
+/// Result of `Connection.Tune` proposed by the mock broker.
+#[derive(Clone, Copy, Debug)]
+pub struct MockTuning {
+    pub channel_max: u16,
+    pub frame_max: u32,
+    pub heartbeat: u16,
+}
+
+
+impl Default for MockTuning {
+    fn default() -> MockTuning {
+        MockTuning {
+            channel_max: 0,
+            frame_max: 128 * 1024,
+            heartbeat: 0,
+        }
+    }
+}
+
+
+/// Listen on a free port of localhost and serve every connection on `handle`.
+/// Returns the address, frames received from clients, and a sender of frames to clients.
+pub fn spawn_broker(
+    tuning: MockTuning,
+    handle: &Handle,
+) -> (SocketAddr, UnboundedReceiver<Frame>, UnboundedSender<Frame>) {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (received_tx, received_rx) = mpsc::unbounded();
+    let (push_tx, push_rx) = mpsc::unbounded();
+
+    // Frames pushed by a test are sent to the first connection.
+    let mut push_rx = Some(push_rx);
+    let handle2 = handle.clone();
+    let accept = listener
+        .incoming()
+        .for_each(move |(socket, _)| {
+            let push_rx = push_rx.take().unwrap_or_else(|| mpsc::unbounded().1);
+            let served = serve(socket, tuning, received_tx.clone(), push_rx)
+                .map_err(|e| println!("Mock broker error : {:?}", e));
+            handle2.spawn(served);
+            Ok(())
+        })
+        .map_err(|e| println!("Mock broker fails to accept : {:?}", e));
+    handle.spawn(accept);
+
+    (addr, received_rx, push_tx)
+}
+
+
+/// Serve a single connection on `socket`.
+pub fn serve<S>(
+    socket: S,
+    tuning: MockTuning,
+    received_tx: UnboundedSender<Frame>,
+    push_rx: UnboundedReceiver<Frame>,
+) -> Box<Future<Item = (), Error = io::Error>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let fut = read_exact(socket, [0u8; 8])
+        .and_then(|(socket, _protocol_header)| {
+            let start = StartMethod {
+                version_major: 0,
+                version_minor: 9,
+                server_properties: HashMap::new(),
+                mechanisms: "PLAIN AMQPLAIN".into(),
+                locales: "en_US".into(),
+            };
+            let start = MethodPayload::Connection(connection::ClassMethod::Start(start));
+            socket.framed(Codec).send(method_frame(0, start))
+        })
+        .and_then(move |socket| {
+            let (outgo, income) = socket.split();
+            let replies = income.filter_map(move |frame| {
+                let reply = reply(&frame, tuning);
+                let _ = received_tx.unbounded_send(frame);
+                reply
+            });
+            let pushed = push_rx.map_err(|()| io::Error::new(io::ErrorKind::Other, "canceled"));
+            outgo.send_all(replies.select(pushed))
+        })
+        .map(|_| ());
+    Box::new(fut)
+}
+
+
+fn reply(frame: &Frame, tuning: MockTuning) -> Option<Frame> {
+    let ch_id = frame.header.channel;
+    let method = match frame.method() {
+        Some(method) => method,
+        None => return None,
+    };
+
+    if let Some(m) = method.connection() {
+        if m.start_ok().is_some() {
+            let tune = TuneMethod {
+                channel_max: tuning.channel_max,
+                frame_max: tuning.frame_max,
+                heartbeat: tuning.heartbeat,
+            };
+            let tune = MethodPayload::Connection(connection::ClassMethod::Tune(tune));
+            return Some(method_frame(0, tune));
+        }
+        if m.open().is_some() {
+            let open_ok = connection::OpenOkMethod { reserved1: "".into() };
+            let open_ok = MethodPayload::Connection(connection::ClassMethod::OpenOk(open_ok));
+            return Some(method_frame(0, open_ok));
+        }
+        if m.close().is_some() {
+            let close_ok = MethodPayload::Connection(connection::ClassMethod::CloseOk);
+            return Some(method_frame(0, close_ok));
+        }
+    }
+
+    if let Some(m) = method.channel() {
+        if m.open().is_some() {
+            let open_ok = channel::OpenOkMethod { reserved1: "".into() };
+            let open_ok = MethodPayload::Channel(channel::ClassMethod::OpenOk(open_ok));
+            return Some(method_frame(ch_id, open_ok));
+        }
+        if m.close().is_some() {
+            let close_ok = MethodPayload::Channel(channel::ClassMethod::CloseOk);
+            return Some(method_frame(ch_id, close_ok));
+        }
+    }
+
+    None
+}
+
+
+pub fn method_frame(channel_id: u16, method: MethodPayload) -> Frame {
+    Frame {
+        header: FrameHeader { channel: channel_id },
+        payload: FramePayload::Method(method),
+    }
+}
+
+
+/// True if `frame` is `Basic.Publish`.
+pub fn is_publish(frame: &Frame) -> bool {
+    frame
+        .method()
+        .and_then(|c| c.basic())
+        .and_then(|m| m.publish())
+        .is_some()
+}
@@ -0,0 +1,117 @@
+//! `connect_tls` against a TLS acceptor on localhost with the self-signed certificate in
+//! `tests/support` (issued for "localhost" and 127.0.0.1, PKCS#12 password "amqpr").
+
+extern crate amqpr;
+extern crate amqpr_api;
+extern crate amqpr_codec;
+extern crate futures;
+extern crate native_tls;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_tls;
+
+mod support;
+
+use futures::{Future, Stream};
+use futures::unsync::mpsc;
+use tokio_core::reactor::Core;
+use tokio_core::net::TcpListener;
+
+use amqpr_api::handshake::SimpleHandshaker;
+
+use amqpr::unsync::{connect_tls, TlsOption, Certificate, Identity};
+
+use std::net::SocketAddr;
+
+use support::MockTuning;
+
+
+const CERT: &'static [u8] = include_bytes!("support/localhost.crt");
+const IDENTITY: &'static [u8] = include_bytes!("support/localhost.p12");
+
+
+/// Accept a single TLS connection and serve it as a mock broker.
+fn spawn_tls_broker(core: &Core) -> SocketAddr {
+    let handle = core.handle();
+    let identity = Identity::from_pkcs12(IDENTITY, "amqpr").unwrap();
+    let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+    let acceptor = tokio_tls::TlsAcceptor::from(acceptor);
+
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle2 = handle.clone();
+    let accept = listener
+        .incoming()
+        .into_future()
+        .map_err(|(e, _)| println!("Fail to accept : {:?}", e))
+        .and_then(move |(socket, _)| {
+            let (socket, _) = socket.unwrap();
+            acceptor.accept(socket).map_err(
+                |e| println!("TLS handshake fails on server : {:?}", e),
+            )
+        })
+        .map(move |socket| {
+            let (received_tx, _received_rx) = mpsc::unbounded();
+            let (_push_tx, push_rx) = mpsc::unbounded();
+            let served = support::serve(socket, MockTuning::default(), received_tx, push_rx);
+            handle2.spawn(served.map_err(|e| println!("Mock broker error : {:?}", e)));
+        });
+    handle.spawn(accept);
+
+    addr
+}
+
+
+fn connect(option: TlsOption) -> Result<(), String> {
+    let mut core = Core::new().unwrap();
+    let addr = spawn_tls_broker(&core);
+    let handshaker = SimpleHandshaker {
+        user: "guest".into(),
+        pass: "guest".into(),
+        virtual_host: "/".into(),
+    };
+    let connected = connect_tls(&addr, handshaker, &option, &core.handle());
+    core.run(connected).map(|_global| ()).map_err(|e| e.to_string())
+}
+
+
+fn trusted_option(domain: &str) -> TlsOption {
+    let mut option = TlsOption::new(domain);
+    option.root_certificates.push(Certificate::from_pem(CERT).unwrap());
+    option
+}
+
+
+#[test]
+fn connect_with_trusted_certificate() {
+    connect(trusted_option("localhost")).unwrap();
+}
+
+
+#[test]
+fn reject_untrusted_certificate() {
+    assert!(connect(TlsOption::new("localhost")).is_err());
+}
+
+
+#[test]
+fn reject_certificate_for_other_host() {
+    assert!(connect(trusted_option("amqp.example.com")).is_err());
+}
+
+
+#[test]
+fn accept_certificate_for_other_host_without_hostname_verification() {
+    let mut option = trusted_option("amqp.example.com");
+    option.verify_hostname = false;
+    connect(option).unwrap();
+}
+
+
+#[test]
+fn still_verify_chain_without_hostname_verification() {
+    let mut option = TlsOption::new("localhost");
+    option.verify_hostname = false;
+    assert!(connect(option).is_err());
+}